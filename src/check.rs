@@ -0,0 +1,583 @@
+use crate::{
+    dns::{CachedResolver, DnsResolver, Lookup, MxResolver, Offline, RecordType, Recorder},
+    git::{Emails, Origin},
+    rules::{CompiledRule, Match, Metadata, Rule, RuleSet, Severity, domain_of},
+};
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// Failure policy for MX lookups that error or time out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsFailure {
+    /// Treat the email as matching the rule
+    Closed,
+    /// Treat the email as not matching the rule
+    Open,
+    /// Report the email as unverified and fail the check
+    Unknown,
+}
+
+/// An email that matched a rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Violation {
+    pub email: String,
+    /// The rule as written in the rules file
+    pub rule: String,
+    /// The rules file the rule is in, unless the rules were parsed from a
+    /// string
+    pub file: Option<PathBuf>,
+    /// 1-based line number of the rule in the rules file
+    pub line: usize,
+    /// For MX rules, the MX host that matched
+    pub mx_host: Option<String>,
+    /// What else matched: the nameserver for `NS-RECORD` rules, the MX
+    /// host's address for `MX-IP` rules, or why the domain cannot receive
+    /// mail for `NO-MAIL` rules
+    pub detail: Option<String>,
+    /// Set when the rule only matched because its lookup failed under
+    /// [`DnsFailure::Closed`]
+    pub lookup_error: Option<String>,
+    /// Commits the email was found in, empty when read from an emails file
+    pub origins: Vec<Origin>,
+    #[serde(flatten)]
+    pub metadata: Metadata,
+}
+
+impl Violation {
+    /// Where the rule is: `file:line`, or `line N` without a file.
+    pub fn location(&self) -> String {
+        location(self.file.as_deref(), self.line)
+    }
+
+    /// A short description of the rule that matched.
+    pub fn reason(&self) -> String {
+        let mut context = vec![self.location()];
+        if let Some(host) = &self.mx_host {
+            context.push(format!("MX {host}"));
+        }
+        context.extend(self.detail.clone());
+        if self.lookup_error.is_some() {
+            context.push("DNS lookup failed".to_string());
+        }
+        format!("rule `{}` ({})", self.rule, context.join(", "))
+    }
+
+    /// The email and why it violates the rule, with the rule's message and
+    /// documentation link if it has them.
+    pub fn describe(&self) -> String {
+        let mut description = format!("{} — {}", self.email, self.reason());
+        if let Some(message) = &self.metadata.message {
+            description.push_str(&format!(": {message}"));
+        }
+        if let Some(url) = &self.metadata.url {
+            description.push_str(&format!(" ({url})"));
+        }
+        description
+    }
+}
+
+/// A rule whose lookup failed for an email, so it could not be evaluated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unverified {
+    pub email: String,
+    /// The rule as written in the rules file
+    pub rule: String,
+    /// The rules file the rule is in, unless the rules were parsed from a
+    /// string
+    pub file: Option<PathBuf>,
+    /// 1-based line number of the rule in the rules file
+    pub line: usize,
+    pub error: String,
+}
+
+impl Unverified {
+    fn new(email: &str, rule: &CompiledRule, error: anyhow::Error) -> Self {
+        Self {
+            email: email.to_string(),
+            rule: rule.source.text.clone(),
+            file: rule.source.file.clone(),
+            line: rule.source.line,
+            error: format!("{error:#}"),
+        }
+    }
+
+    /// Where the rule is: `file:line`, or `line N` without a file.
+    pub fn location(&self) -> String {
+        location(self.file.as_deref(), self.line)
+    }
+
+    /// The email and the rule that could not be evaluated against it.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} — rule `{}` ({})",
+            self.email,
+            self.rule,
+            self.location()
+        )
+    }
+}
+
+/// A rule that was not evaluated against an email, because it needs DNS
+/// and the check runs offline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Skipped {
+    pub email: String,
+    /// The rule as written in the rules file
+    pub rule: String,
+    /// The rules file the rule is in, unless the rules were parsed from a
+    /// string
+    pub file: Option<PathBuf>,
+    /// 1-based line number of the rule in the rules file
+    pub line: usize,
+}
+
+impl Skipped {
+    fn new(email: &str, rule: &CompiledRule) -> Self {
+        Self {
+            email: email.to_string(),
+            rule: rule.source.text.clone(),
+            file: rule.source.file.clone(),
+            line: rule.source.line,
+        }
+    }
+
+    /// Where the rule is: `file:line`, or `line N` without a file.
+    pub fn location(&self) -> String {
+        location(self.file.as_deref(), self.line)
+    }
+
+    /// The email and the rule that was skipped for it.
+    pub fn describe(&self) -> String {
+        format!(
+            "{} — rule `{}` ({})",
+            self.email,
+            self.rule,
+            self.location()
+        )
+    }
+}
+
+fn location(file: Option<&Path>, line: usize) -> String {
+    match file {
+        Some(file) => format!("{}:{line}", file.display()),
+        None => format!("line {line}"),
+    }
+}
+
+/// The outcome of checking a set of emails.
+#[derive(Debug)]
+pub struct Report {
+    /// Every email that was checked, sorted
+    pub checked: Vec<String>,
+    /// Sorted by email
+    pub violations: Vec<Violation>,
+    /// Rule lookups that failed, regardless of how the policy resolved them
+    pub unverified: Vec<Unverified>,
+    /// Rules skipped because they need DNS and the check runs offline
+    pub not_evaluated: Vec<Skipped>,
+    pub dns_failure: DnsFailure,
+}
+
+impl Report {
+    /// Violations of the given severity.
+    pub fn violations_of(&self, severity: Severity) -> impl Iterator<Item = &Violation> {
+        self.violations
+            .iter()
+            .filter(move |v| v.metadata.severity == severity)
+    }
+
+    /// Whether any violation has [`Severity::Error`], failing the check.
+    pub fn has_errors(&self) -> bool {
+        self.violations_of(Severity::Error).next().is_some()
+    }
+
+    /// Whether every rule was evaluated against every email, and none of
+    /// them reported a violation.
+    pub fn is_clean(&self) -> bool {
+        self.violations.is_empty() && self.unverified.is_empty() && self.not_evaluated.is_empty()
+    }
+}
+
+/// Evaluates emails against a [`RuleSet`].
+pub struct Checker {
+    rules: RuleSet,
+    dns_failure: DnsFailure,
+    resolver: Box<dyn MxResolver>,
+    dns_workers: usize,
+}
+
+impl Checker {
+    /// A checker that resolves MX rules through the system nameservers.
+    pub fn new(rules: RuleSet) -> Self {
+        Checker {
+            rules,
+            dns_failure: DnsFailure::Unknown,
+            resolver: Box::new(DnsResolver::system()),
+            dns_workers: 8,
+        }
+    }
+
+    pub fn resolver(mut self, resolver: impl MxResolver + 'static) -> Self {
+        self.resolver = Box::new(resolver);
+        self
+    }
+
+    pub fn dns_failure(mut self, dns_failure: DnsFailure) -> Self {
+        self.dns_failure = dns_failure;
+        self
+    }
+
+    /// Number of domains to resolve concurrently, 8 by default.
+    pub fn dns_workers(mut self, dns_workers: usize) -> Self {
+        self.dns_workers = dns_workers;
+        self
+    }
+
+    /// Check every email, attaching its origins to any violation.
+    ///
+    /// The records the rules need are resolved once per domain,
+    /// concurrently, before any rule is evaluated.
+    pub fn check(&self, emails: Emails) -> Report {
+        let resolver = CachedResolver::new(self.resolver.as_ref());
+        let kinds: Vec<_> = self
+            .rules
+            .rules()
+            .iter()
+            .flat_map(|r| r.rule.lookups().iter().copied())
+            .collect();
+        if !kinds.is_empty() {
+            let domains = emails.keys().map(|e| domain_of(e));
+            resolver.prefetch(domains, &kinds, self.dns_workers);
+        }
+        // `MX-IP` rules also need the addresses of every MX host
+        if self
+            .rules
+            .rules()
+            .iter()
+            .any(|r| matches!(r.rule, Rule::MxIp(_)))
+        {
+            let hosts: Vec<_> = emails
+                .keys()
+                .filter_map(|e| resolver.mx_hosts(domain_of(e)).ok())
+                .flatten()
+                .filter(|host| !host.is_empty())
+                .collect();
+            let hosts = hosts.iter().map(String::as_str);
+            resolver.prefetch(hosts, &[RecordType::Address], self.dns_workers);
+        }
+        find_violations(emails, self.rules.rules(), self.dns_failure, &resolver)
+    }
+
+    /// Check a single email.
+    pub fn check_email(&self, email: &str) -> Verdict {
+        let resolver = CachedResolver::new(self.resolver.as_ref());
+        check_email(email, self.rules.rules(), self.dns_failure, &resolver)
+    }
+
+    /// Evaluate every rule against a single email, recording what each
+    /// one looked up, along with the overall verdict.
+    pub fn explain(&self, email: &str) -> Explanation<'_> {
+        let resolver = CachedResolver::new(self.resolver.as_ref());
+        let rules = self
+            .rules
+            .rules()
+            .iter()
+            .map(|rule| {
+                let recorder = Recorder::new(&resolver);
+                let result = rule.rule.find_match(email, &recorder);
+                RuleTrace {
+                    rule,
+                    result,
+                    lookups: recorder.take(),
+                }
+            })
+            .collect();
+        let verdict = check_email(email, self.rules.rules(), self.dns_failure, &resolver);
+        Explanation { rules, verdict }
+    }
+}
+
+/// How every rule evaluates against one email.
+pub struct Explanation<'a> {
+    /// Every rule, in order, allow rules included
+    pub rules: Vec<RuleTrace<'a>>,
+    /// The outcome [`Checker::check_email`] reports for the email
+    pub verdict: Verdict,
+}
+
+/// One rule evaluated on its own against an email.
+pub struct RuleTrace<'a> {
+    pub rule: &'a CompiledRule,
+    /// Whether the rule matched, or why it could not be evaluated
+    pub result: Result<Option<Match>>,
+    /// The DNS lookups the rule made, with their answers
+    pub lookups: Vec<Lookup>,
+}
+
+/// The outcome of checking a single email.
+#[derive(Debug, Default)]
+pub struct Verdict {
+    pub violation: Option<Violation>,
+    /// Rule lookups that failed
+    pub unverified: Vec<Unverified>,
+    /// Rules skipped because they need DNS and the check runs offline
+    pub not_evaluated: Vec<Skipped>,
+}
+
+fn find_violations(
+    commit_emails: Emails,
+    regex_rules: &[CompiledRule],
+    dns_failure: DnsFailure,
+    resolver: &dyn MxResolver,
+) -> Report {
+    let mut checked = Vec::new();
+    let mut violations = Vec::new();
+    let mut unverified = Vec::new();
+    let mut not_evaluated = Vec::new();
+    for (email, mut origins) in commit_emails {
+        let verdict = check_email(&email, regex_rules, dns_failure, resolver);
+        if let Some(mut violation) = verdict.violation {
+            origins.sort_unstable();
+            violation.origins = origins;
+            violations.push(violation);
+        }
+        unverified.extend(verdict.unverified);
+        not_evaluated.extend(verdict.not_evaluated);
+        checked.push(email);
+    }
+
+    checked.sort_unstable();
+    violations.sort_unstable_by(|a, b| a.email.cmp(&b.email));
+    unverified.sort_unstable_by(|a, b| (&a.email, a.line).cmp(&(&b.email, b.line)));
+    not_evaluated.sort_unstable_by(|a, b| (&a.email, a.line).cmp(&(&b.email, b.line)));
+    Report {
+        checked,
+        violations,
+        unverified,
+        not_evaluated,
+        dns_failure,
+    }
+}
+
+/// Evaluate one email: allow rules first, then deny rules in file order.
+///
+/// The violation is the first deny rule that matches, unless a later one
+/// has a higher severity.
+///
+/// A failed lookup counts as a match under [`DnsFailure::Closed`] for deny
+/// rules and under [`DnsFailure::Open`] for allow rules, and as no match
+/// otherwise. Rules skipped offline never match, but while an allow rule
+/// is skipped no violation is reported, since the rule might have allowed
+/// the email.
+fn check_email(
+    email: &str,
+    rules: &[CompiledRule],
+    dns_failure: DnsFailure,
+    resolver: &dyn MxResolver,
+) -> Verdict {
+    let mut verdict = Verdict::default();
+    for rule in rules.iter().filter(|re| re.allow) {
+        match rule.rule.find_match(email, resolver) {
+            Result::Ok(Some(_)) => return Verdict::default(),
+            Result::Ok(None) => {}
+            Err(e) if e.is::<Offline>() => verdict.not_evaluated.push(Skipped::new(email, rule)),
+            Err(e) => verdict.unverified.push(Unverified::new(email, rule, e)),
+        }
+    }
+    if dns_failure == DnsFailure::Open && !verdict.unverified.is_empty() {
+        return verdict;
+    }
+    let allow_skipped = !verdict.not_evaluated.is_empty();
+
+    for rule in rules.iter().filter(|re| !re.allow) {
+        let current = verdict.violation.as_ref().map(|v| v.metadata.severity);
+        if current.is_some_and(|severity| severity <= rule.metadata.severity) {
+            continue;
+        }
+        let (mx_host, detail, lookup_error) = match rule.rule.find_match(email, resolver) {
+            Result::Ok(None) => continue,
+            Result::Ok(Some(Match::Pattern)) => (None, None, None),
+            Result::Ok(Some(Match::MxHost(host))) => (Some(host), None, None),
+            Result::Ok(Some(Match::NoMail(reason))) => (None, Some(reason), None),
+            Result::Ok(Some(Match::MxAddress { host, ip })) => {
+                (Some(host), Some(format!("address {ip}")), None)
+            }
+            Result::Ok(Some(Match::NsHost(host))) => (None, Some(format!("NS {host}")), None),
+            Err(e) if e.is::<Offline>() => {
+                verdict.not_evaluated.push(Skipped::new(email, rule));
+                continue;
+            }
+            Err(e) => {
+                let unverified = Unverified::new(email, rule, e);
+                let error = unverified.error.clone();
+                verdict.unverified.push(unverified);
+                if dns_failure != DnsFailure::Closed {
+                    continue;
+                }
+                (None, None, Some(error))
+            }
+        };
+        verdict.violation = Some(Violation {
+            email: email.to_string(),
+            rule: rule.source.text.clone(),
+            file: rule.source.file.clone(),
+            line: rule.source.line,
+            mx_host,
+            detail,
+            lookup_error,
+            origins: Vec::new(),
+            metadata: rule.metadata.clone(),
+        });
+        if rule.metadata.severity == Severity::Error {
+            break;
+        }
+    }
+    if allow_skipped {
+        verdict.violation = None;
+    }
+    verdict
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        Checker, Emails, RuleSet,
+        dns::{MxResolver, OfflineResolver, RecordType},
+        rules::Match,
+    };
+    use anyhow::Result;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Answers every domain with `mx.example.com` and counts lookups.
+    #[derive(Default)]
+    struct Counting(AtomicUsize);
+
+    impl MxResolver for Counting {
+        fn lookup(&self, _: &str, _: RecordType) -> Result<Vec<String>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(vec!["mx.example.com".to_string()])
+        }
+    }
+
+    #[test]
+    fn test_checker() {
+        let rules: RuleSet = "*@gmail.com\n!release-bot@gmail.com\n".parse().unwrap();
+        let checker = Checker::new(rules);
+
+        let verdict = checker.check_email("Me@Gmail.com");
+        assert_eq!(verdict.violation.unwrap().line, 1);
+        assert!(verdict.unverified.is_empty());
+        assert!(
+            checker
+                .check_email("release-bot@gmail.com")
+                .violation
+                .is_none()
+        );
+
+        let emails: Emails = [("a@gmail.com".to_string(), Vec::new())].into();
+        let report = checker.check(emails);
+        assert_eq!(report.checked, ["a@gmail.com"]);
+        assert_eq!(report.violations[0].rule, "*@gmail.com");
+    }
+
+    #[test]
+    fn test_explain() {
+        let rules: RuleSet = "*@gmail.com\nMX-RECORD,mx.example.com\n".parse().unwrap();
+        let counting = Arc::new(Counting::default());
+        let checker = Checker::new(rules).resolver(counting.clone());
+
+        let explanation = checker.explain("a@example.org");
+        let [glob, mx] = &explanation.rules[..] else {
+            panic!("expected a trace per rule");
+        };
+        assert!(matches!(glob.result, Result::Ok(None)));
+        assert!(glob.lookups.is_empty());
+        assert!(matches!(mx.result, Result::Ok(Some(Match::MxHost(_)))));
+        assert_eq!(mx.lookups[0].kind, RecordType::Mx);
+        assert_eq!(mx.lookups[0].domain, "example.org");
+        assert_eq!(
+            mx.lookups[0].answer,
+            Result::Ok(vec!["mx.example.com".into()])
+        );
+        assert_eq!(explanation.verdict.violation.unwrap().line, 2);
+        assert_eq!(counting.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_mx_lookup_once_per_domain() {
+        let counting = Arc::new(Counting::default());
+        let rules = "MX-RECORD,mx.other.com\nMX-RECORD,mx.example.com\n";
+        let checker = Checker::new(rules.parse().unwrap())
+            .resolver(counting.clone())
+            .dns_workers(4);
+
+        let emails: Emails = ["a@one.com", "b@one.com", "c@ONE.com", "d@two.com"]
+            .map(|e| (e.to_string(), Vec::new()))
+            .into();
+        let report = checker.check(emails);
+        assert_eq!(report.violations.len(), 4);
+        assert_eq!(counting.0.load(Ordering::SeqCst), 2);
+
+        // `MX-IP` adds one address lookup per domain and per MX host
+        let counting = Arc::new(Counting::default());
+        let checker = Checker::new("MX-IP,192.0.2.0/24".parse().unwrap())
+            .resolver(counting.clone())
+            .dns_workers(4);
+        let emails: Emails = ["a@one.com", "b@two.com"]
+            .map(|e| (e.to_string(), Vec::new()))
+            .into();
+        checker.check(emails);
+        assert_eq!(counting.0.load(Ordering::SeqCst), 5);
+    }
+
+    #[test]
+    fn test_severity() {
+        let rules = RuleSet::from_toml(
+            "[[rule]]\npattern = '*@gmail.com'\nseverity = 'warning'\n\
+             [[rule]]\npattern = '*@localhost'\n\
+             [[rule]]\npattern = 'root@*'\n",
+        )
+        .unwrap();
+        let checker = Checker::new(rules);
+        let line = |email| checker.check_email(email).violation.unwrap().line;
+        assert_eq!(line("me@gmail.com"), 1);
+        // a later error outranks an earlier warning
+        assert_eq!(line("root@gmail.com"), 6);
+
+        let emails: Emails = ["me@gmail.com", "ci@localhost"]
+            .map(|e| (e.to_string(), Vec::new()))
+            .into();
+        assert!(checker.check(emails).has_errors());
+        let emails: Emails = [("me@gmail.com".to_string(), Vec::new())].into();
+        assert!(!checker.check(emails).has_errors());
+    }
+
+    #[test]
+    fn test_offline() {
+        let rules = "MX-RECORD,mx.example.com\n*@gmail.com\n";
+        let checker = Checker::new(rules.parse().unwrap()).resolver(OfflineResolver);
+
+        let emails: Emails = ["a@gmail.com", "b@example.com"]
+            .map(|e| (e.to_string(), Vec::new()))
+            .into();
+        let report = checker.check(emails);
+        assert_eq!(report.violations.len(), 1);
+        assert_eq!(report.violations[0].line, 2);
+        assert!(report.unverified.is_empty());
+        assert_eq!(report.not_evaluated.len(), 2);
+        assert_eq!(report.not_evaluated[1].email, "b@example.com");
+
+        // an allow rule that can't be checked offline might allow the email
+        let rules = "*@gmail.com\n!MX-SUFFIX,google.com\n";
+        let checker = Checker::new(rules.parse().unwrap()).resolver(OfflineResolver);
+        let verdict = checker.check_email("a@gmail.com");
+        assert!(verdict.violation.is_none());
+        assert_eq!(verdict.not_evaluated[0].line, 2);
+    }
+}
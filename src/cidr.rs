@@ -0,0 +1,102 @@
+//! IP address blocks for `MX-IP` rules, written as `192.0.2.0/24` or
+//! `2001:db8::/32`. A bare address is a block of one.
+
+use anyhow::{Context, Result, bail};
+use std::{fmt, net::IpAddr, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` lies in the block. IPv4 and IPv6 never match each other.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                mask(u32::from(net).into(), self.prefix, 32)
+                    == mask(u32::from(*ip).into(), self.prefix, 32)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                mask(net.into(), self.prefix, 128) == mask((*ip).into(), self.prefix, 128)
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether every address in `other` lies in this block.
+    pub fn contains_block(&self, other: &Cidr) -> bool {
+        self.prefix <= other.prefix && self.contains(&other.addr)
+    }
+}
+
+/// Keep the top `prefix` bits of a `bits`-wide address.
+fn mask(addr: u128, prefix: u8, bits: u32) -> u128 {
+    match u32::from(prefix) {
+        0 => 0,
+        prefix => addr >> (bits - prefix),
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .with_context(|| format!("invalid IP address '{addr}'"))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(prefix) if prefix <= bits => prefix,
+                _ => bail!("invalid prefix length '{prefix}'"),
+            },
+            None => bits,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Cidr;
+
+    fn contains(cidr: &str, ip: &str) -> bool {
+        cidr.parse::<Cidr>().unwrap().contains(&ip.parse().unwrap())
+    }
+
+    #[test]
+    fn test_contains() {
+        assert!(contains("192.0.2.0/24", "192.0.2.200"));
+        assert!(!contains("192.0.2.0/24", "192.0.3.1"));
+        assert!(contains("192.0.2.7", "192.0.2.7"));
+        assert!(!contains("192.0.2.7", "192.0.2.8"));
+        assert!(contains("0.0.0.0/0", "203.0.113.9"));
+        assert!(contains("2001:db8::/32", "2001:db8:ffff::1"));
+        assert!(!contains("2001:db8::/32", "2001:db9::1"));
+        assert!(!contains("::/0", "192.0.2.1"));
+
+        let block = |s: &str| s.parse::<Cidr>().unwrap();
+        assert!(block("192.0.2.0/24").contains_block(&block("192.0.2.128/25")));
+        assert!(!block("192.0.2.128/25").contains_block(&block("192.0.2.0/24")));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!("192.0.2.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("mx.example.com".parse::<Cidr>().is_err());
+        assert!("192.0.2.0/".parse::<Cidr>().is_err());
+    }
+}
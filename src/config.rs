@@ -0,0 +1,314 @@
+//! Defaults for command-line options from `.check-commits.toml` files.
+//!
+//! Options come from, in increasing order of precedence: the user config at
+//! `$XDG_CONFIG_HOME/check-commits/config.toml` (or
+//! `~/.config/check-commits/config.toml`), the `.check-commits.toml` at the
+//! root of the repository, and the command line.
+
+use crate::{Args, Output, parse_nameserver};
+use anyhow::{Context, Ok, Result, anyhow};
+use check_commits_email::{DnsFailure, git};
+use clap::{ArgMatches, parser::ValueSource};
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+/// Name of the config file at the root of a repository.
+pub const FILE_NAME: &str = ".check-commits.toml";
+
+/// Options as written in a config file, named after their flags. Relative
+/// paths are relative to the config file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<Output>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_failure: Option<DnsFailure>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nameserver: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_fixture: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_cache: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offline: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_workers: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report_only: Option<bool>,
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let mut config: Config = toml::from_str(&text)
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+
+        let dir = path.parent().unwrap_or(Path::new(""));
+        for rules in &mut config.rules {
+            *rules = dir.join(&rules);
+        }
+        for file in [&mut config.dns_fixture, &mut config.dns_cache]
+            .into_iter()
+            .flatten()
+        {
+            *file = dir.join(&file);
+        }
+        Ok(config)
+    }
+
+    /// The options of both configs, preferring `other`'s where both set one.
+    ///
+    /// Options of `self` that conflict with one `other` sets are dropped.
+    pub fn merge(mut self, other: Config) -> Config {
+        if !other.nameserver.is_empty() {
+            self.offline = None;
+            self.dns_fixture = None;
+        }
+        if other.dns_fixture.is_some() {
+            self.nameserver.clear();
+            self.dns_cache = None;
+        }
+        if other.dns_cache.is_some() {
+            self.dns_fixture = None;
+        }
+        if other.offline == Some(true) {
+            self.nameserver.clear();
+        }
+        Config {
+            rules: or_vec(self.rules, other.rules),
+            output: other.output.or(self.output),
+            dns_failure: other.dns_failure.or(self.dns_failure),
+            nameserver: or_vec(self.nameserver, other.nameserver),
+            dns_fixture: other.dns_fixture.or(self.dns_fixture),
+            dns_cache: other.dns_cache.or(self.dns_cache),
+            offline: other.offline.or(self.offline),
+            dns_workers: other.dns_workers.or(self.dns_workers),
+            report_only: other.report_only.or(self.report_only),
+        }
+    }
+
+    /// Fill in every option of `args` that was not given on the command line,
+    /// nor conflicts with one that was.
+    pub fn apply(self, args: &mut Args, matches: &ArgMatches) -> Result<()> {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        let offline = args.offline && !unset("offline");
+        if unset("rules") && !self.rules.is_empty() {
+            args.rules = self.rules;
+        }
+        if unset("output")
+            && let Some(output) = self.output
+        {
+            args.output = output;
+        }
+        if unset("dns_failure")
+            && let Some(dns_failure) = self.dns_failure
+        {
+            args.dns_failure = dns_failure;
+        }
+        if unset("nameserver") && unset("dns_fixture") && !offline && !self.nameserver.is_empty() {
+            args.nameserver = self
+                .nameserver
+                .iter()
+                .map(|s| parse_nameserver(s).map_err(|e| anyhow!(e)))
+                .collect::<Result<_>>()?;
+        }
+        if unset("dns_fixture")
+            && unset("nameserver")
+            && unset("dns_cache")
+            && self.dns_fixture.is_some()
+        {
+            args.dns_fixture = self.dns_fixture;
+        }
+        if unset("dns_cache") && unset("dns_fixture") && self.dns_cache.is_some() {
+            args.dns_cache = self.dns_cache;
+        }
+        if unset("offline")
+            && unset("no_offline")
+            && unset("nameserver")
+            && let Some(offline) = self.offline
+        {
+            args.offline = offline;
+        }
+        if unset("dns_workers")
+            && let Some(dns_workers) = self.dns_workers
+        {
+            args.dns_workers = dns_workers;
+        }
+        if unset("report_only")
+            && unset("no_report_only")
+            && let Some(report_only) = self.report_only
+        {
+            args.report_only = report_only;
+        }
+        Ok(())
+    }
+
+    /// The effective options of `args`, for `--print-config`.
+    pub fn from_args(args: &Args) -> Config {
+        Config {
+            rules: args.rules.clone(),
+            output: Some(args.output),
+            dns_failure: Some(args.dns_failure),
+            nameserver: args.nameserver.iter().map(ToString::to_string).collect(),
+            dns_fixture: args.dns_fixture.clone(),
+            dns_cache: args.dns_cache.clone(),
+            offline: Some(args.offline),
+            dns_workers: Some(args.dns_workers),
+            report_only: Some(args.report_only),
+        }
+    }
+}
+
+fn or_vec<T>(a: Vec<T>, b: Vec<T>) -> Vec<T> {
+    if b.is_empty() { a } else { b }
+}
+
+/// The config files that exist, lowest precedence first.
+///
+/// The repository is `repo`, or else the one the current directory is in.
+pub fn discover(repo: Option<&Path>) -> Vec<PathBuf> {
+    let user = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => env::var_os("HOME").map(|home| Path::new(&home).join(".config")),
+    }
+    .map(|dir| dir.join("check-commits/config.toml"));
+    let project = git::toplevel(repo.unwrap_or(Path::new(".")))
+        .ok()
+        .map(|root| root.join(FILE_NAME));
+    [user, project]
+        .into_iter()
+        .flatten()
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, FILE_NAME, discover};
+    use crate::{Args, Output};
+    use check_commits_email::DnsFailure;
+    use clap::{CommandFactory, FromArgMatches};
+    use std::{fs, path::Path, process::Command};
+
+    fn apply(config: Config, argv: &[&str]) -> Args {
+        let argv = ["check-commits"].iter().chain(argv);
+        let matches = Args::command().try_get_matches_from(argv).unwrap();
+        let mut args = Args::from_arg_matches(&matches).unwrap();
+        config.apply(&mut args, &matches).unwrap();
+        args
+    }
+
+    #[test]
+    fn test_precedence() {
+        let user: Config = toml::from_str("output = 'github'\ndns-workers = 3").unwrap();
+        let project: Config =
+            toml::from_str("rules = ['org.txt']\noutput = 'json'\nnameserver = ['10.0.0.53']")
+                .unwrap();
+        let config = user.merge(project);
+
+        let args = apply(config.clone(), &["-e", "emails.txt"]);
+        assert_eq!(args.rules, [Path::new("org.txt")]);
+        assert_eq!(args.output, Output::Json);
+        assert_eq!(args.dns_workers, 3);
+        assert_eq!(args.nameserver[0].to_string(), "10.0.0.53:53");
+        assert_eq!(args.dns_failure, DnsFailure::Unknown);
+
+        // flags win, even when they repeat the default
+        let args = apply(
+            config.clone(),
+            &[
+                "-e",
+                "emails.txt",
+                "-r",
+                "mine.txt",
+                "-o",
+                "text",
+                "--dns-workers",
+                "8",
+            ],
+        );
+        assert_eq!(args.rules, [Path::new("mine.txt")]);
+        assert_eq!(args.output, Output::Text);
+        assert_eq!(args.dns_workers, 8);
+
+        let args = apply(config, &["explain", "a@example.com", "-r", "mine.txt"]);
+        assert_eq!(args.rules, [Path::new("mine.txt")]);
+
+        // config options that conflict with a flag are dropped, either way
+        let offline: Config = toml::from_str(
+            "offline = true
+dns-fixture = 'mx.txt'
+report-only = true",
+        )
+        .unwrap();
+        let args = apply(
+            offline.clone(),
+            &["-e", "e.txt", "--nameserver", "192.0.2.1"],
+        );
+        assert!(!args.offline);
+        assert_eq!(args.dns_fixture, None);
+        assert!(args.report_only);
+        let args = apply(offline.clone(), &["-e", "e.txt", "--dns-cache", "dns.json"]);
+        assert_eq!(args.dns_fixture, None);
+        assert!(args.offline);
+        let args = apply(
+            offline,
+            &["-e", "e.txt", "--no-offline", "--no-report-only"],
+        );
+        assert!(!args.offline);
+        assert!(!args.report_only);
+
+        let online: Config = toml::from_str(
+            "nameserver = ['10.0.0.53']
+dns-cache = 'dns.json'",
+        )
+        .unwrap();
+        let args = apply(online.clone(), &["-e", "e.txt", "--offline"]);
+        assert!(args.nameserver.is_empty());
+        let args = apply(online, &["-e", "e.txt", "--dns-fixture", "mx.txt"]);
+        assert!(args.nameserver.is_empty());
+        assert_eq!(args.dns_cache, None);
+
+        let merged = Config {
+            offline: Some(true),
+            ..Config::default()
+        }
+        .merge(toml::from_str("nameserver = ['10.0.0.53']").unwrap());
+        assert_eq!(merged.offline, None);
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        fs::create_dir_all(repo.join("sub")).unwrap();
+        let status = Command::new("git")
+            .args(["init", "-q"])
+            .arg(&repo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::write(
+            repo.join(FILE_NAME),
+            "rules = ['rules.txt']\ndns-cache = '.cache/dns.json'\n",
+        )
+        .unwrap();
+
+        let files = discover(Some(&repo.join("sub")));
+        let file = files.last().unwrap();
+        assert_eq!(file.file_name().unwrap(), FILE_NAME);
+        let config = Config::from_file(file).unwrap();
+        assert!(config.rules[0].ends_with("repo/rules.txt"));
+        assert!(config.dns_cache.unwrap().ends_with("repo/.cache/dns.json"));
+
+        fs::write(repo.join(FILE_NAME), "rule = ['rules.txt']\n").unwrap();
+        assert!(Config::from_file(&repo.join(FILE_NAME)).is_err());
+    }
+}
@@ -0,0 +1,702 @@
+use anyhow::{Context, Ok, Result, anyhow};
+use hickory_resolver::{
+    TokioAsyncResolver,
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    proto::op::ResponseCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::runtime::{self, Runtime};
+
+/// The kinds of DNS records a rule can depend on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordType {
+    /// Mail exchangers, as host names
+    Mx,
+    /// A and AAAA records, as IP addresses
+    Address,
+    /// Nameservers, as host names
+    Ns,
+}
+
+impl fmt::Display for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RecordType::Mx => "MX",
+            RecordType::Address => "A",
+            RecordType::Ns => "NS",
+        })
+    }
+}
+
+/// Looks up the mail-related DNS records of a domain.
+pub trait MxResolver: Send + Sync {
+    /// Records of type `kind` for `domain`. Host names are lowercase and
+    /// without the trailing dot, so a null MX (RFC 7505) is the empty host.
+    ///
+    /// A domain without such records yields an empty list rather than an
+    /// error, and one that does not exist fails with [`NxDomain`].
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>>;
+
+    /// Like [`lookup`](Self::lookup), along with how long the answer may be
+    /// cached, if known.
+    fn lookup_with_ttl(
+        &self,
+        domain: &str,
+        kind: RecordType,
+    ) -> Result<(Vec<String>, Option<Duration>)> {
+        Ok((self.lookup(domain, kind)?, None))
+    }
+
+    /// MX hosts of `domain`.
+    fn mx_hosts(&self, domain: &str) -> Result<Vec<String>> {
+        self.lookup(domain, RecordType::Mx)
+    }
+}
+
+impl<T: MxResolver + ?Sized> MxResolver for Arc<T> {
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+        (**self).lookup(domain, kind)
+    }
+
+    fn lookup_with_ttl(
+        &self,
+        domain: &str,
+        kind: RecordType,
+    ) -> Result<(Vec<String>, Option<Duration>)> {
+        (**self).lookup_with_ttl(domain, kind)
+    }
+}
+
+impl<T: MxResolver + ?Sized> MxResolver for Box<T> {
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+        (**self).lookup(domain, kind)
+    }
+
+    fn lookup_with_ttl(
+        &self,
+        domain: &str,
+        kind: RecordType,
+    ) -> Result<(Vec<String>, Option<Duration>)> {
+        (**self).lookup_with_ttl(domain, kind)
+    }
+}
+
+/// Resolves over the network, through the system or explicit nameservers.
+///
+/// The underlying resolver is created on first use, so a broken system
+/// configuration surfaces as a lookup error. Lookups may be issued from
+/// several threads at once.
+pub struct DnsResolver {
+    config: Option<ResolverConfig>,
+    resolver: OnceLock<Result<(Runtime, TokioAsyncResolver), String>>,
+}
+
+impl DnsResolver {
+    /// Use the nameservers from the system configuration (`/etc/resolv.conf`).
+    pub fn system() -> Self {
+        DnsResolver {
+            config: None,
+            resolver: OnceLock::new(),
+        }
+    }
+
+    /// Use only the given nameservers.
+    pub fn with_nameservers(nameservers: &[SocketAddr]) -> Self {
+        let mut group = NameServerConfigGroup::new();
+        for addr in nameservers {
+            group.merge(NameServerConfigGroup::from_ips_clear(
+                &[addr.ip()],
+                addr.port(),
+                true,
+            ));
+        }
+        DnsResolver {
+            config: Some(ResolverConfig::from_parts(None, Vec::new(), group)),
+            resolver: OnceLock::new(),
+        }
+    }
+
+    fn resolver(&self) -> Result<&(Runtime, TokioAsyncResolver)> {
+        self.resolver
+            .get_or_init(|| connect(self.config.as_ref()).map_err(|e| format!("{e:#}")))
+            .as_ref()
+            .map_err(|e| anyhow!("{e}"))
+    }
+}
+
+fn connect(config: Option<&ResolverConfig>) -> Result<(Runtime, TokioAsyncResolver)> {
+    let runtime = runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .context("failed to start DNS runtime")?;
+    let resolver = {
+        let _guard = runtime.enter();
+        match config {
+            Some(config) => TokioAsyncResolver::tokio(config.clone(), ResolverOpts::default()),
+            None => TokioAsyncResolver::tokio_from_system_conf()
+                .context("failed to read system DNS configuration")?,
+        }
+    };
+    Ok((runtime, resolver))
+}
+
+impl MxResolver for DnsResolver {
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+        Ok(self.lookup_with_ttl(domain, kind)?.0)
+    }
+
+    fn lookup_with_ttl(
+        &self,
+        domain: &str,
+        kind: RecordType,
+    ) -> Result<(Vec<String>, Option<Duration>)> {
+        let (runtime, resolver) = self.resolver()?;
+        let answer = runtime.block_on(async {
+            Result::<_, ResolveError>::Ok(match kind {
+                RecordType::Mx => {
+                    let lookup = resolver.mx_lookup(domain).await?;
+                    let hosts = lookup
+                        .iter()
+                        .map(|mx| normalize(&mx.exchange().to_ascii()))
+                        .collect();
+                    (hosts, lookup.valid_until())
+                }
+                RecordType::Address => {
+                    let lookup = resolver.lookup_ip(domain).await?;
+                    let ips = lookup.iter().map(|ip| ip.to_string()).collect();
+                    (ips, lookup.valid_until())
+                }
+                RecordType::Ns => {
+                    let lookup = resolver.ns_lookup(domain).await?;
+                    let hosts = lookup.iter().map(|ns| normalize(&ns.to_ascii())).collect();
+                    (hosts, lookup.valid_until())
+                }
+            })
+        });
+        match answer {
+            Result::Ok((records, valid_until)) => {
+                let ttl = valid_until.saturating_duration_since(Instant::now());
+                Ok((records, Some(ttl)))
+            }
+            Err(e) => match e.kind() {
+                ResolveErrorKind::NoRecordsFound {
+                    negative_ttl,
+                    response_code,
+                    ..
+                } => {
+                    let ttl = negative_ttl.map(|ttl| Duration::from_secs(ttl.into()));
+                    if *response_code == ResponseCode::NXDomain {
+                        return Err(NxDomain { ttl }.into());
+                    }
+                    Ok((Vec::new(), ttl))
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
+
+/// Answers from a fixture file instead of the network.
+///
+/// Each line holds a domain followed by its MX hosts, separated by
+/// whitespace. A record type of `MX`, `NS`, `A` or `AAAA` after the domain gives
+/// records of that type instead, and `.` alone is a null MX. A domain
+/// followed by `NXDOMAIN` does not exist. Blank lines and lines starting with
+/// `#` are ignored, and domains not listed have no records.
+///
+/// ```text
+/// itsusinn.eu.org route1.mx.cloudflare.net route2.mx.cloudflare.net
+/// example.com A 93.184.215.14
+/// example.com NS ns1.example.com ns2.example.com
+/// example.net MX .
+/// exmaple.com NXDOMAIN
+/// ```
+#[derive(Debug, Default)]
+pub struct StaticResolver {
+    records: HashMap<(RecordType, String), Vec<String>>,
+    nonexistent: HashSet<String>,
+}
+
+impl StaticResolver {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        fs::read_to_string(path)
+            .with_context(|| format!("failed to read DNS fixture {}", path.display()))?
+            .parse()
+    }
+}
+
+impl FromStr for StaticResolver {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut records: HashMap<_, Vec<_>> = HashMap::new();
+        let mut nonexistent = HashSet::new();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace().peekable();
+            let Some(domain) = fields.next() else {
+                continue;
+            };
+            let kind = match fields.peek() {
+                Some(&"MX") => RecordType::Mx,
+                Some(&"A" | &"AAAA") => RecordType::Address,
+                Some(&"NS") => RecordType::Ns,
+                Some(&"NXDOMAIN") => {
+                    nonexistent.insert(normalize(domain));
+                    continue;
+                }
+                _ => {
+                    let entry = records.entry((RecordType::Mx, normalize(domain)));
+                    entry.or_default().extend(fields.map(normalize));
+                    continue;
+                }
+            };
+            fields.next();
+            records
+                .entry((kind, normalize(domain)))
+                .or_default()
+                .extend(fields.map(normalize));
+        }
+        Ok(StaticResolver {
+            records,
+            nonexistent,
+        })
+    }
+}
+
+impl MxResolver for StaticResolver {
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+        if self.nonexistent.contains(&normalize(domain)) {
+            return Err(NxDomain { ttl: None }.into());
+        }
+        Ok(self
+            .records
+            .get(&(kind, normalize(domain)))
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// The error for lookups skipped because the check runs offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Offline;
+
+impl fmt::Display for Offline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("not evaluated (offline)")
+    }
+}
+
+impl std::error::Error for Offline {}
+
+/// The error for lookups of a domain that does not exist (NXDOMAIN).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NxDomain {
+    /// How long the answer may be cached, if known
+    pub ttl: Option<Duration>,
+}
+
+impl fmt::Display for NxDomain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("domain does not exist")
+    }
+}
+
+impl std::error::Error for NxDomain {}
+
+/// Never touches the network; every lookup fails with [`Offline`].
+///
+/// Wrap it in a [`DiskCache`] to answer offline from a saved cache.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OfflineResolver;
+
+impl MxResolver for OfflineResolver {
+    fn lookup(&self, _: &str, _: RecordType) -> Result<Vec<String>> {
+        Err(Offline.into())
+    }
+}
+
+/// Remembers the answers of another resolver, once per domain and record
+/// type.
+///
+/// Failed lookups are remembered too, so every rule sees the same answer.
+pub struct CachedResolver<'a> {
+    inner: &'a dyn MxResolver,
+    cache: Mutex<HashMap<(RecordType, String), CachedAnswer>>,
+}
+
+type CachedAnswer = Result<Vec<String>, CachedError>;
+
+#[derive(Debug, Clone)]
+enum CachedError {
+    Offline,
+    NxDomain,
+    Failed(String),
+}
+
+impl From<anyhow::Error> for CachedError {
+    fn from(e: anyhow::Error) -> Self {
+        if e.is::<Offline>() {
+            CachedError::Offline
+        } else if e.is::<NxDomain>() {
+            CachedError::NxDomain
+        } else {
+            CachedError::Failed(format!("{e:#}"))
+        }
+    }
+}
+
+impl From<CachedError> for anyhow::Error {
+    fn from(e: CachedError) -> Self {
+        match e {
+            CachedError::Offline => Offline.into(),
+            CachedError::NxDomain => NxDomain { ttl: None }.into(),
+            CachedError::Failed(e) => anyhow!("{e}"),
+        }
+    }
+}
+
+impl<'a> CachedResolver<'a> {
+    pub fn new(inner: &'a dyn MxResolver) -> Self {
+        CachedResolver {
+            inner,
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Resolve the records of every `kinds` for every uncached domain up
+    /// front, on at most `workers` threads.
+    pub fn prefetch<'d>(
+        &self,
+        domains: impl IntoIterator<Item = &'d str>,
+        kinds: &[RecordType],
+        workers: usize,
+    ) {
+        let cache = self.cache.lock().unwrap();
+        let pending: HashSet<_> = domains
+            .into_iter()
+            .map(normalize)
+            .flat_map(|domain| kinds.iter().map(move |&kind| (kind, domain.clone())))
+            .filter(|key| !cache.contains_key(key))
+            .collect();
+        drop(cache);
+
+        let workers = workers.clamp(1, pending.len().max(1));
+        let queue = Mutex::new(pending.into_iter());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    loop {
+                        let Some((kind, domain)) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        let _ = self.lookup(&domain, kind);
+                    }
+                });
+            }
+        });
+    }
+}
+
+impl MxResolver for CachedResolver<'_> {
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+        let key = (kind, normalize(domain));
+        if let Some(answer) = self.cache.lock().unwrap().get(&key) {
+            return answer.clone().map_err(Into::into);
+        }
+        let answer = self.inner.lookup(&key.1, kind).map_err(CachedError::from);
+        self.cache.lock().unwrap().insert(key, answer.clone());
+        answer.map_err(Into::into)
+    }
+}
+
+/// A lookup made through a [`Recorder`], with its answer or error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lookup {
+    pub kind: RecordType,
+    pub domain: String,
+    pub answer: Result<Vec<String>, String>,
+}
+
+/// Passes lookups through to another resolver and keeps a log of them.
+pub struct Recorder<'a> {
+    inner: &'a dyn MxResolver,
+    log: Mutex<Vec<Lookup>>,
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(inner: &'a dyn MxResolver) -> Self {
+        Recorder {
+            inner,
+            log: Mutex::default(),
+        }
+    }
+
+    /// The lookups made so far, in order, emptying the log.
+    pub fn take(&self) -> Vec<Lookup> {
+        std::mem::take(&mut self.log.lock().unwrap())
+    }
+}
+
+impl MxResolver for Recorder<'_> {
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+        let answer = self.inner.lookup(domain, kind);
+        self.log.lock().unwrap().push(Lookup {
+            kind,
+            domain: normalize(domain),
+            answer: match &answer {
+                Result::Ok(records) => Result::Ok(records.clone()),
+                Err(e) => Err(format!("{e:#}")),
+            },
+        });
+        answer
+    }
+}
+
+/// Persists another resolver's answers in a JSON file until their TTL expires.
+///
+/// Entries are keyed by record type and domain, e.g. `MX example.com`.
+/// Answers without a TTL and failed lookups are never persisted, but a
+/// domain that does not exist is, for its negative TTL. Call
+/// [`save`](Self::save) after checking to write the file back.
+pub struct DiskCache {
+    inner: Box<dyn MxResolver>,
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    hosts: Vec<String>,
+    /// The domain does not exist
+    #[serde(default, skip_serializing_if = "is_false")]
+    nxdomain: bool,
+    /// Unix timestamp, in seconds, after which the entry is stale
+    expires: u64,
+}
+
+impl DiskCache {
+    /// Load the cache at `path`, starting empty if it does not exist yet.
+    pub fn open(path: impl Into<PathBuf>, inner: impl MxResolver + 'static) -> Result<Self> {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            Result::Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("invalid DNS cache {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read DNS cache {}", path.display()));
+            }
+        };
+        Ok(DiskCache {
+            inner: Box::new(inner),
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Write the unexpired entries back to the cache file.
+    pub fn save(&self) -> Result<()> {
+        let now = unix_now();
+        let mut entries = self.entries.lock().unwrap().clone();
+        entries.retain(|_, entry| entry.expires > now);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&entries)?)
+            .with_context(|| format!("failed to write DNS cache {}", self.path.display()))
+    }
+}
+
+impl MxResolver for DiskCache {
+    fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+        Ok(self.lookup_with_ttl(domain, kind)?.0)
+    }
+
+    fn lookup_with_ttl(
+        &self,
+        domain: &str,
+        kind: RecordType,
+    ) -> Result<(Vec<String>, Option<Duration>)> {
+        let domain = normalize(domain);
+        let key = format!("{kind} {domain}");
+        let now = unix_now();
+        if let Some(entry) = self.entries.lock().unwrap().get(&key)
+            && entry.expires > now
+        {
+            let ttl = Some(Duration::from_secs(entry.expires - now));
+            if entry.nxdomain {
+                return Err(NxDomain { ttl }.into());
+            }
+            return Ok((entry.hosts.clone(), ttl));
+        }
+
+        let (hosts, ttl, nxdomain) = match self.inner.lookup_with_ttl(&domain, kind) {
+            Result::Ok((hosts, ttl)) => (hosts, ttl, None),
+            Err(e) => match e.downcast_ref::<NxDomain>() {
+                Some(&nxdomain) => (Vec::new(), nxdomain.ttl, Some(e)),
+                None => return Err(e),
+            },
+        };
+        if let Some(ttl) = ttl.filter(|ttl| !ttl.is_zero()) {
+            let entry = CacheEntry {
+                hosts: hosts.clone(),
+                nxdomain: nxdomain.is_some(),
+                expires: now + ttl.as_secs(),
+            };
+            self.entries.lock().unwrap().insert(key, entry);
+        }
+        match nxdomain {
+            Some(e) => Err(e),
+            None => Ok((hosts, ttl)),
+        }
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Lowercase a host name and strip its trailing dot.
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DiskCache, MxResolver, NxDomain, RecordType, StaticResolver};
+    use anyhow::{Result, bail};
+    use std::time::Duration;
+
+    /// Answers with a fixed host and TTL, or fails every lookup.
+    struct Fixed(Option<Duration>);
+
+    impl MxResolver for Fixed {
+        fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+            Ok(self.lookup_with_ttl(domain, kind)?.0)
+        }
+
+        fn lookup_with_ttl(
+            &self,
+            _: &str,
+            _: RecordType,
+        ) -> Result<(Vec<String>, Option<Duration>)> {
+            match self.0 {
+                Some(ttl) => Ok((vec!["mx.example.com".to_string()], Some(ttl))),
+                None => bail!("lookup failed"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_disk_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache/dns.json");
+
+        let cache = DiskCache::open(&path, Fixed(Some(Duration::from_secs(300)))).unwrap();
+        assert_eq!(cache.mx_hosts("Example.com.").unwrap(), ["mx.example.com"]);
+        cache.save().unwrap();
+
+        // a fresh run answers from the file even though lookups now fail
+        let cache = DiskCache::open(&path, Fixed(None)).unwrap();
+        let (hosts, ttl) = cache
+            .lookup_with_ttl("example.com", RecordType::Mx)
+            .unwrap();
+        assert_eq!(hosts, ["mx.example.com"]);
+        assert!(ttl.unwrap() <= Duration::from_secs(300));
+        assert!(cache.mx_hosts("other.com").is_err());
+    }
+
+    #[test]
+    fn test_disk_cache_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dns.json");
+        std::fs::write(
+            &path,
+            r#"{"MX example.com":{"hosts":["old.example.com"],"expires":1}}"#,
+        )
+        .unwrap();
+
+        let cache = DiskCache::open(&path, Fixed(Some(Duration::from_secs(60)))).unwrap();
+        assert_eq!(cache.mx_hosts("example.com").unwrap(), ["mx.example.com"]);
+    }
+
+    /// Every domain does not exist, for a minute.
+    struct Nonexistent;
+
+    impl MxResolver for Nonexistent {
+        fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+            Ok(self.lookup_with_ttl(domain, kind)?.0)
+        }
+
+        fn lookup_with_ttl(
+            &self,
+            _: &str,
+            _: RecordType,
+        ) -> Result<(Vec<String>, Option<Duration>)> {
+            let ttl = Some(Duration::from_secs(60));
+            Err(NxDomain { ttl }.into())
+        }
+    }
+
+    #[test]
+    fn test_disk_cache_nxdomain() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dns.json");
+
+        let cache = DiskCache::open(&path, Nonexistent).unwrap();
+        assert!(cache.mx_hosts("exmaple.com").unwrap_err().is::<NxDomain>());
+        cache.save().unwrap();
+
+        let cache = DiskCache::open(&path, Fixed(None)).unwrap();
+        assert!(cache.mx_hosts("exmaple.com").unwrap_err().is::<NxDomain>());
+    }
+
+    #[test]
+    fn test_static_resolver() {
+        let fixture: StaticResolver = "example.com mx1.example.com MX2.example.com.\n\
+             example.com A 192.0.2.1\n\
+             example.com AAAA 2001:db8::1\n\
+             example.net MX .\n"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            fixture.mx_hosts("example.com").unwrap(),
+            ["mx1.example.com", "mx2.example.com"]
+        );
+        assert_eq!(
+            fixture.lookup("example.com", RecordType::Address).unwrap(),
+            ["192.0.2.1", "2001:db8::1"]
+        );
+        assert_eq!(fixture.mx_hosts("example.net").unwrap(), [""]);
+        assert!(fixture.mx_hosts("example.org").unwrap().is_empty());
+
+        let fixture: StaticResolver = "exmaple.com NXDOMAIN
+"
+        .parse()
+        .unwrap();
+        let error = fixture.mx_hosts("Exmaple.com.").unwrap_err();
+        assert!(error.is::<NxDomain>());
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{Ok, Result, bail};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// Commit emails mapped to the places they were seen.
+pub type Emails = HashMap<String, Vec<Origin>>;
+
+/// Which identity of a commit or tag an email came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Author,
+    Committer,
+    Tagger,
+}
+
+/// A commit (or annotated tag) that carries an email.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Origin {
+    /// Full SHA of the commit; for taggers, the commit the tag points at
+    pub commit: String,
+    pub role: Role,
+    /// Tag name, for tagger identities
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+            Role::Tagger => "tagger",
+        })
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let short = self.commit.get(..7).unwrap_or(&self.commit);
+        match &self.tag {
+            Some(tag) => write!(f, "tag {tag} ({short}, {})", self.role),
+            None => write!(f, "{short} ({})", self.role),
+        }
+    }
+}
+
+/// Collect every author, committer and tagger email reachable in `range`.
+///
+/// Tagger identities are taken from annotated tags that point at one of
+/// the commits in the range.
+pub fn read_emails(repo: &Path, range: &str) -> Result<Emails> {
+    let mut emails = Emails::new();
+    let mut commits = HashSet::new();
+    let mut add = |email: &str, origin: Origin| {
+        if !email.is_empty() {
+            emails.entry(email.to_string()).or_default().push(origin);
+        }
+    };
+
+    let log = git(
+        repo,
+        &[
+            "log",
+            "--format=%H%x00%ae%x00%ce",
+            "--end-of-options",
+            range,
+            "--",
+        ],
+    )?;
+    for line in log.lines() {
+        let mut fields = line.split('\0');
+        let (Some(sha), Some(author), Some(committer)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        commits.insert(sha.to_string());
+        for (email, role) in [(author, Role::Author), (committer, Role::Committer)] {
+            let origin = Origin {
+                commit: sha.to_string(),
+                role,
+                tag: None,
+            };
+            add(email, origin);
+        }
+    }
+
+    let tags = git(
+        repo,
+        &[
+            "for-each-ref",
+            "--format=%(refname:short)%00%(*objectname)%00%(taggeremail)",
+            "refs/tags",
+        ],
+    )?;
+    for line in tags.lines() {
+        let mut fields = line.split('\0');
+        let (Some(tag), Some(target), Some(tagger)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        // lightweight tags have neither a peeled object nor a tagger
+        if commits.contains(target) {
+            let origin = Origin {
+                commit: target.to_string(),
+                role: Role::Tagger,
+                tag: Some(tag.to_string()),
+            };
+            add(tagger.trim_matches(['<', '>']), origin);
+        }
+    }
+
+    Ok(emails)
+}
+
+/// The root of the working tree that `dir` is in.
+pub fn toplevel(dir: &Path) -> Result<PathBuf> {
+    Ok(PathBuf::from(
+        git(dir, &["rev-parse", "--show-toplevel"])?.trim_end(),
+    ))
+}
+
+fn git(repo: &Path, args: &[&str]) -> Result<String> {
+    let Output {
+        status,
+        stdout,
+        stderr,
+    } = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()?;
+    if !status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(stdout)?)
+}
@@ -0,0 +1,144 @@
+//! Wildcard patterns for plain rules.
+//!
+//! A pattern must match the whole email, ignoring case:
+//!
+//! | Syntax    | Matches                                          |
+//! | --------- | ------------------------------------------------ |
+//! | `*`       | any run of characters, including none            |
+//! | `?`       | exactly one character                            |
+//! | `[abc]`   | one of the listed characters                     |
+//! | `[a-z]`   | one character in the range                       |
+//! | `[!abc]`  | one character not listed (`[^abc]` also works)   |
+//! | `\c`      | the character `c` literally, e.g. `\*` or `\[`   |
+//!
+//! Every other character matches itself. A `]` right after the opening
+//! `[` (or `[!`) is part of the class.
+
+use anyhow::{Result, bail};
+use regex::Regex;
+
+/// Compile a wildcard pattern into a case-insensitive, fully anchored regex.
+pub fn compile(pattern: &str) -> Result<Regex> {
+    Ok(Regex::new(&to_regex(pattern)?)?)
+}
+
+/// Translate a wildcard pattern into regex syntax.
+pub fn to_regex(pattern: &str) -> Result<String> {
+    let mut regex = String::from("(?i)^");
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => regex.push_str(&class(&mut chars, pattern)?),
+            '\\' => match chars.next() {
+                Some(c) => push_literal(&mut regex, c),
+                None => bail!("trailing `\\` in pattern '{pattern}'"),
+            },
+            c => push_literal(&mut regex, c),
+        }
+    }
+    regex.push('$');
+    Ok(regex)
+}
+
+/// Translate a character class, after its opening `[`.
+fn class(chars: &mut std::str::Chars, pattern: &str) -> Result<String> {
+    let mut class = String::from("[");
+    if matches!(chars.clone().next(), Some('!' | '^')) {
+        class.push('^');
+        chars.next();
+    }
+
+    let mut first = true;
+    loop {
+        let Some(c) = chars.next() else {
+            bail!("unclosed `[` in pattern '{pattern}'");
+        };
+        let c = match c {
+            ']' if !first => break,
+            '\\' => match chars.next() {
+                Some(c) => c,
+                None => bail!("trailing `\\` in pattern '{pattern}'"),
+            },
+            c => c,
+        };
+        first = false;
+        push_literal(&mut class, c);
+
+        // a range, unless the `-` is the last character of the class
+        let mut ahead = chars.clone();
+        if ahead.next() == Some('-')
+            && let Some(end) = ahead.next()
+            && end != ']'
+        {
+            let end = match end {
+                '\\' => match ahead.next() {
+                    Some(end) => end,
+                    None => bail!("trailing `\\` in pattern '{pattern}'"),
+                },
+                end => end,
+            };
+            if end < c {
+                bail!("invalid range `{c}-{end}` in pattern '{pattern}'");
+            }
+            class.push('-');
+            push_literal(&mut class, end);
+            *chars = ahead;
+        }
+    }
+    class.push(']');
+    Ok(class)
+}
+
+fn push_literal(regex: &mut String, c: char) {
+    regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+}
+
+#[cfg(test)]
+mod test {
+    use super::compile;
+
+    fn matches(pattern: &str, email: &str) -> bool {
+        compile(pattern).unwrap().is_match(email)
+    }
+
+    #[test]
+    fn test_anchored() {
+        assert!(matches("*@qq.com", "a@qq.com"));
+        assert!(matches("*@qq.com", "A@QQ.COM"));
+        assert!(!matches("*@qq.com", "a@qq.com.evil.org"));
+        assert!(!matches("*@qq.com", "a@qqxcom"));
+        assert!(matches("*", ""));
+    }
+
+    #[test]
+    fn test_metacharacters() {
+        assert!(matches("a+b@example.com", "a+b@example.com"));
+        assert!(!matches("a+b@example.com", "aab@example.com"));
+        assert!(matches("(x)|$@example.com", "(x)|$@example.com"));
+        assert!(matches("a\\*b@x.org", "a*b@x.org"));
+        assert!(!matches("a\\*b@x.org", "axxb@x.org"));
+    }
+
+    #[test]
+    fn test_wildcards_and_classes() {
+        assert!(matches("user?@x.org", "user1@x.org"));
+        assert!(!matches("user?@x.org", "user@x.org"));
+        assert!(matches("[abc]@x.org", "b@x.org"));
+        assert!(!matches("[abc]@x.org", "d@x.org"));
+        assert!(matches("[0-9][0-9]@x.org", "42@x.org"));
+        assert!(matches("[!0-9]*@x.org", "bob@x.org"));
+        assert!(!matches("[^0-9]*@x.org", "1bob@x.org"));
+        assert!(matches("[]-]@x.org", "]@x.org"));
+        assert!(matches("[]-]@x.org", "-@x.org"));
+        assert!(matches("[&&~]@x.org", "~@x.org"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(compile("[abc@x.org").is_err());
+        assert!(compile("[z-a]@x.org").is_err());
+        assert!(compile("abc\\").is_err());
+    }
+}
@@ -0,0 +1,40 @@
+//! Validate git commit emails against wildcard rules.
+//!
+//! Build a [`RuleSet`] from a rules file or string, then evaluate emails
+//! with a [`Checker`]:
+//!
+//! ```
+//! use check_commits_email::{Checker, RuleSet};
+//!
+//! let rules: RuleSet = "*@hotmail.com".parse().unwrap();
+//! let verdict = Checker::new(rules).check_email("abc@hotmail.com");
+//! assert_eq!(verdict.violation.unwrap().rule, "*@hotmail.com");
+//! ```
+
+mod check;
+pub mod cidr;
+pub mod dns;
+pub mod git;
+pub mod glob;
+pub mod lint;
+pub mod rules;
+pub mod sarif;
+
+pub use check::{
+    Checker, DnsFailure, Explanation, Report, RuleTrace, Skipped, Unverified, Verdict, Violation,
+};
+pub use git::{Emails, Origin};
+pub use rules::{Metadata, RuleError, RuleSet, Severity};
+
+use anyhow::{Ok, Result};
+use std::{fs, path::Path};
+
+/// Read an emails file, one address per line.
+pub fn read_emails(path: impl AsRef<Path>) -> Result<Emails> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|s| (s.to_string(), Vec::new()))
+        .collect())
+}
@@ -0,0 +1,430 @@
+//! Static checks of rules files, without checking any emails.
+//!
+//! Besides rules that fail to compile, the linter flags rules that are
+//! repeated, shadowed by an earlier rule, or can never match, and `REGEX`
+//! rules that are not anchored to the whole address.
+
+use crate::rules::{
+    Entry, Rule, RuleIds, RuleSource, Severity, domain_of, is_within, normalize_domain, parse_rule,
+    read_rules, text_entries, toml_entries,
+};
+use anyhow::{Context, Ok, Result, anyhow, bail};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+/// A problem with a rule, or with a rules file as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The rules file, unless the rules were linted from a string
+    pub file: Option<PathBuf>,
+    /// 1-based line number in the rules file
+    pub line: usize,
+    /// The rule as written, if the problem is with a single rule
+    pub rule: Option<String>,
+    pub message: String,
+}
+
+impl Finding {
+    fn new(source: &RuleSource, message: impl Into<String>) -> Self {
+        Finding {
+            file: source.file.clone(),
+            line: source.line,
+            rule: Some(source.text.trim().to_string()),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}: ", file.display(), self.line)?,
+            None => write!(f, "line {}: ", self.line)?,
+        }
+        match &self.rule {
+            Some(rule) => write!(f, "rule `{rule}`: {}", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Lint rules files as one set, in order, following their includes.
+///
+/// Fails only if one of `paths` cannot be read; problems with included
+/// files are findings on the `include` line.
+pub fn lint_files<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Result<Vec<Finding>> {
+    let mut linter = Linter::default();
+    for path in paths {
+        linter.file(path.as_ref())?;
+    }
+    Ok(linter.finish())
+}
+
+/// Lint the rules of a plain rules file.
+pub fn lint_str(text: &str) -> Vec<Finding> {
+    let mut linter = Linter::default();
+    linter.duplicate_lines(text, None);
+    linter.entries(text_entries(text, None), None);
+    linter.finish()
+}
+
+/// A rule that compiled, as the linter sees it.
+struct Linted {
+    source: RuleSource,
+    allow: bool,
+    /// e.g. `MX-RECORD`, or `GLOB` for plain rules
+    kind: String,
+    pattern: String,
+    rule: Rule,
+    severity: Severity,
+}
+
+#[derive(Default)]
+struct Linter {
+    /// The files being read, outermost first, as canonical paths
+    stack: Vec<PathBuf>,
+    visited: HashSet<PathBuf>,
+    /// Rule ids across every file, which must be unique in the whole set
+    ids: RuleIds,
+    rules: Vec<Linted>,
+    findings: Vec<Finding>,
+}
+
+impl Linter {
+    fn file(&mut self, path: &Path) -> Result<()> {
+        let canonical = fs::canonicalize(path)
+            .with_context(|| format!("failed to read rules file {}", path.display()))?;
+        if self.stack.contains(&canonical) {
+            bail!("include cycle through {}", path.display());
+        }
+        if !self.visited.insert(canonical.clone()) {
+            return Ok(());
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read rules file {}", path.display()))?;
+
+        let entries = if path.extension().is_some_and(|ext| ext == "toml") {
+            match toml_entries(&text, Some(path), &mut self.ids) {
+                Result::Ok(entries) => entries,
+                Err(e) => {
+                    let span = e.downcast_ref::<toml::de::Error>().and_then(|e| e.span());
+                    self.findings.push(Finding {
+                        file: Some(path.to_path_buf()),
+                        line: span.map_or(1, |span| line_of(&text, span.start)),
+                        rule: None,
+                        message: format!("invalid TOML: {}", e.to_string().trim()),
+                    });
+                    return Ok(());
+                }
+            }
+        } else {
+            self.duplicate_lines(&text, Some(path));
+            text_entries(&text, Some(path))
+        };
+        self.stack.push(canonical);
+        self.entries(entries, Some(path.parent().unwrap_or(Path::new(""))));
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Report repeated lines of a plain rules file, which loading drops.
+    fn duplicate_lines(&mut self, text: &str, file: Option<&Path>) {
+        let mut seen = HashMap::new();
+        for mut source in read_rules(text) {
+            source.file = file.map(Path::to_path_buf);
+            match seen.get(&source.text) {
+                Some(line) => {
+                    let message = format!("duplicate of line {line}");
+                    self.findings.push(Finding::new(&source, message));
+                }
+                None => {
+                    seen.insert(source.text.clone(), source.line);
+                }
+            }
+        }
+    }
+
+    /// Compile `entries`, following includes relative to `dir`.
+    fn entries(&mut self, entries: Vec<Entry>, dir: Option<&Path>) {
+        for entry in entries {
+            match entry {
+                Entry::Include { source, path } => {
+                    let included = match dir {
+                        Some(dir) => self.file(&dir.join(&path)),
+                        None => Err(anyhow!("includes are only allowed in rules files")),
+                    };
+                    if let Err(e) = included {
+                        self.findings.push(Finding::new(&source, format!("{e:#}")));
+                    }
+                }
+                Entry::Rule {
+                    source,
+                    compiled: Err(e),
+                    ..
+                } => {
+                    let message = format!("invalid rule: {e:#}");
+                    self.findings.push(Finding::new(&source, message));
+                }
+                Entry::Rule {
+                    source,
+                    metadata,
+                    compiled: Result::Ok((allow, rule)),
+                } => {
+                    let (_, kind, pattern) = parse_rule(&source.text);
+                    self.rules.push(Linted {
+                        allow,
+                        kind: kind.to_string(),
+                        pattern: pattern.to_string(),
+                        rule,
+                        severity: metadata.severity,
+                        source,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Check the compiled rules against each other, and return every
+    /// finding in file and line order.
+    fn finish(mut self) -> Vec<Finding> {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if let Some(message) = check_rule(rule).or_else(|| compare(rule, &self.rules, i)) {
+                self.findings.push(Finding::new(&rule.source, message));
+            }
+        }
+        self.findings
+            .sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+        self.findings
+    }
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+/// Problems with a rule on its own.
+fn check_rule(rule: &Linted) -> Option<String> {
+    match rule.kind.as_str() {
+        "GLOB" if is_literal(&rule.pattern) && !rule.pattern.contains('@') => {
+            Some("never matches: the pattern has no `@`".to_string())
+        }
+        "REGEX" if !is_anchored(&rule.pattern) => Some(
+            "unanchored pattern: it matches anywhere in the address, \
+             add `^` and `$` to match the whole address"
+                .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+/// Problems with rule `i` given the other rules: a repeat or a shadow of an
+/// earlier rule, or a deny rule every match of which is allowed.
+fn compare(rule: &Linted, rules: &[Linted], i: usize) -> Option<String> {
+    for earlier in &rules[..i] {
+        if earlier.allow != rule.allow {
+            continue;
+        }
+        if same(earlier, rule) {
+            return Some(format!("duplicate of {}", location(earlier, rule)));
+        }
+        // a more severe deny rule is reported over an earlier, milder one
+        if covers(earlier, rule) && (rule.allow || earlier.severity <= rule.severity) {
+            return Some(format!("shadowed by {}", location(earlier, rule)));
+        }
+    }
+    if !rule.allow
+        && let Some(allow) = rules.iter().find(|a| a.allow && covers(a, rule))
+    {
+        return Some(format!(
+            "never matches: every address it matches is allowed by {}",
+            location(allow, rule)
+        ));
+    }
+    None
+}
+
+/// Where `other` is, as seen from `rule`.
+fn location(other: &Linted, rule: &Linted) -> String {
+    match &other.source.file {
+        Some(file) if other.source.file != rule.source.file => {
+            format!("{}:{}", file.display(), other.source.line)
+        }
+        _ => format!("line {}", other.source.line),
+    }
+}
+
+fn same(a: &Linted, b: &Linted) -> bool {
+    a.kind == b.kind
+        && match a.kind.as_str() {
+            "REGEX" => a.pattern == b.pattern,
+            _ => a.pattern.trim().eq_ignore_ascii_case(b.pattern.trim()),
+        }
+}
+
+/// Whether `a` matches every address `b` matches, as far as can be told
+/// without DNS.
+fn covers(a: &Linted, b: &Linted) -> bool {
+    if a.kind == "GLOB" && a.pattern.chars().all(|c| c == '*') {
+        return true;
+    }
+    // `*@example.com` is the same as `DOMAIN,example.com`
+    if let (Some(domain), Rule::Domain(d)) = (glob_domain(a), &b.rule)
+        && domain == *d
+    {
+        return true;
+    }
+    let literal = is_literal(&b.pattern);
+    let address = b.pattern.trim();
+    let host = normalize_domain(&b.pattern);
+    match (&a.rule, b.kind.as_str()) {
+        // plain rules ignore case, so the regex has to match in every case
+        (Rule::Regex(regex), "GLOB") => {
+            literal
+                && [address.to_lowercase(), address.to_uppercase()]
+                    .iter()
+                    .all(|address| regex.is_match(address))
+        }
+        (Rule::Domain(domain), "GLOB") => {
+            literal && normalize_domain(domain_of(address)) == *domain
+        }
+        (Rule::DomainSuffix(suffix), "GLOB") => {
+            literal && is_within(&normalize_domain(domain_of(address)), suffix)
+        }
+        (Rule::Domain(domain), _) => matches!(&b.rule, Rule::Domain(d) if d == domain),
+        (Rule::DomainSuffix(suffix), _) => {
+            matches!(&b.rule, Rule::Domain(d) | Rule::DomainSuffix(d) if is_within(d, suffix))
+        }
+        (Rule::MxRecord(regex), "MX-RECORD") => literal && regex.is_match(&host),
+        (Rule::MxSuffix(suffix), "MX-RECORD") => literal && is_within(&host, suffix),
+        (Rule::MxSuffix(suffix), _) => {
+            matches!(&b.rule, Rule::MxSuffix(s) if is_within(s, suffix))
+        }
+        (Rule::MxIp(block), _) => matches!(&b.rule, Rule::MxIp(b) if block.contains_block(b)),
+        (Rule::NsRecord(regex), "NS-RECORD") => literal && regex.is_match(&host),
+        _ => false,
+    }
+}
+
+/// The domain of a plain rule of the form `*@example.com`.
+fn glob_domain(rule: &Linted) -> Option<String> {
+    let domain = rule.pattern.trim().strip_prefix("*@")?;
+    (rule.kind == "GLOB" && is_literal(domain)).then(|| normalize_domain(domain))
+}
+
+/// Whether a wildcard pattern has no wildcards.
+fn is_literal(pattern: &str) -> bool {
+    !pattern.contains(['*', '?', '[', '\\'])
+}
+
+/// Whether a regex can only match the whole address.
+fn is_anchored(pattern: &str) -> bool {
+    let mut pattern = pattern.trim();
+    // leading flag groups such as `(?i)`
+    while let Some(rest) = pattern.strip_prefix("(?")
+        && let Some((flags, rest)) = rest.split_once(')')
+        && flags.chars().all(|c| c.is_ascii_alphabetic() || c == '-')
+    {
+        pattern = rest;
+    }
+    (pattern.starts_with('^') || pattern.starts_with("\\A"))
+        && ((pattern.ends_with('$') && !pattern.ends_with("\\$")) || pattern.ends_with("\\z"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{lint_files, lint_str};
+    use std::fs;
+
+    fn lint(text: &str) -> Vec<String> {
+        lint_str(text).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        let findings = lint(
+            "*@gmail.com\n\
+             bob@gmail.com\n\
+             REGEX,gmail\\.com\n\
+             REGEX,(?i)^[a-z]+@qq\\.com$\n\
+             MX-RECORD,\n\
+             hotmail.com\n\
+             *@gmail.com\n\
+             !*@corp.com\n\
+             DOMAIN,corp.com\n\
+             DOMAIN-SUFFIX,example.org\n\
+             DOMAIN,mail.example.org\n\
+             [abc@x.org\n\
+             REGEX,^ann@foxmail\\.com$\n\
+             ann@foxmail.com\n\
+             REGEX,(?i)^eve@foxmail\\.com$\n\
+             eve@foxmail.com\n",
+        );
+        assert_eq!(
+            findings,
+            [
+                "line 2: rule `bob@gmail.com`: shadowed by line 1",
+                "line 3: rule `REGEX,gmail\\.com`: unanchored pattern: it matches anywhere \
+                 in the address, add `^` and `$` to match the whole address",
+                "line 5: rule `MX-RECORD,`: invalid rule: empty MX target",
+                "line 6: rule `hotmail.com`: never matches: the pattern has no `@`",
+                "line 7: rule `*@gmail.com`: duplicate of line 1",
+                "line 9: rule `DOMAIN,corp.com`: never matches: every address it matches \
+                 is allowed by line 8",
+                "line 11: rule `DOMAIN,mail.example.org`: shadowed by line 10",
+                "line 12: rule `[abc@x.org`: invalid rule: unclosed `[` in pattern '[abc@x.org'",
+                "line 16: rule `eve@foxmail.com`: shadowed by line 15",
+            ]
+        );
+        assert!(lint("*@gmail.com\nMX-SUFFIX,mx.example.com\nNO-MAIL\n").is_empty());
+    }
+
+    #[test]
+    fn test_lint_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let org = dir.path().join("org.toml");
+        fs::write(
+            &org,
+            "[[rule]]\npattern = '*@gmail.com'\nseverity = 'warning'\n\n\
+             [[rule]]\nkind = 'MX-SUFFIX'\npattern = 'mx.example.com'\n",
+        )
+        .unwrap();
+        let repo = dir.path().join("rules.txt");
+        fs::write(
+            &repo,
+            "include org.toml\nroot@gmail.com\nMX-SUFFIX,eu.mx.example.com\ninclude missing.txt\n",
+        )
+        .unwrap();
+
+        let findings = lint_files([&repo]).unwrap();
+        assert_eq!(findings.len(), 2);
+        // more severe than the warning it overlaps, so not shadowed
+        assert_eq!(findings[0].line, 3);
+        assert!(findings[0].message.ends_with("org.toml:5"));
+        assert_eq!(findings[1].line, 4);
+        assert!(findings[1].message.starts_with("failed to read rules file"));
+
+        fs::write(&org, "[[rule]\n").unwrap();
+        let findings = lint_files([&repo]).unwrap();
+        assert_eq!(findings[0].file.as_deref(), Some(org.as_path()));
+        assert!(findings[0].message.starts_with("invalid TOML"));
+
+        fs::write(&org, "[[rule]]\nid = 'a'\npattern = '*@gmail.com'\n").unwrap();
+        let other = dir.path().join("other.toml");
+        fs::write(&other, "[[rule]]\nid = 'a'\npattern = '*@qq.com'\n").unwrap();
+        let findings = lint_files([&repo, &other]).unwrap();
+        let finding = findings
+            .iter()
+            .find(|f| f.file.as_deref() == Some(other.as_path()))
+            .unwrap();
+        assert!(
+            finding
+                .message
+                .starts_with("invalid rule: duplicate rule id 'a', first used at ")
+        );
+
+        assert!(lint_files([dir.path().join("none.txt")]).is_err());
+    }
+}
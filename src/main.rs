@@ -1,15 +1,25 @@
+mod config;
+
 use anyhow::{Ok, Result};
-use clap::Parser;
-use hickory_resolver::{
-    Resolver,
-    config::{ResolverConfig, ResolverOpts},
+use check_commits_email::{
+    Checker, DnsFailure, Explanation, Report, RuleSet, Severity,
+    dns::{DiskCache, DnsResolver, MxResolver, Offline, OfflineResolver, StaticResolver},
+    git, lint, read_emails,
+    rules::{CompiledRule, Match},
+    sarif,
+};
+use clap::{
+    ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum, error::ErrorKind,
 };
-use regex::Regex;
+use config::Config;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashSet,
-    fs,
+    env, fs,
+    io::Write,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
-    sync::LazyLock,
+    process::{self, ExitCode},
+    sync::Arc,
 };
 
 #[derive(Parser, Debug)]
@@ -17,202 +27,1157 @@ use std::{
     name = "check-commits",
     version = "0.1.0",
     about = "Git commit email validator",
-    long_about = "Validate git commit emails against wildcard rules"
+    long_about = "Validate git commit emails against wildcard rules",
+    subcommand_negates_reqs = true
 )]
 struct Args {
-    /// Path to email blacklist file
-    #[arg(short, long)]
-    rules: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to rules file, read as TOML if it ends in `.toml` (repeatable,
+    /// in order)
+    #[arg(short, long, global = true)]
+    rules: Vec<PathBuf>,
 
     /// Path to commit emails file
-    #[arg(short, long)]
-    emails: PathBuf,
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["repo", "print_config"],
+        conflicts_with = "repo"
+    )]
+    emails: Option<PathBuf>,
+
+    /// Path to a git repository to read commit emails from
+    #[arg(long)]
+    repo: Option<PathBuf>,
+
+    /// Revision range to walk when reading from --repo
+    #[arg(long, requires = "repo", default_value = "HEAD")]
+    range: String,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+
+    /// How to treat emails whose DNS lookup failed
+    #[arg(long, value_enum, default_value_t = DnsFailure::Unknown, global = true)]
+    dns_failure: DnsFailure,
+
+    /// Nameserver to resolve MX rules with, as IP or IP:port (repeatable)
+    #[arg(long, value_parser = parse_nameserver, global = true)]
+    nameserver: Vec<SocketAddr>,
+
+    /// Resolve MX rules from a fixture file of `domain mx-host...` lines
+    #[arg(long, conflicts_with = "nameserver", global = true)]
+    dns_fixture: Option<PathBuf>,
+
+    /// Reuse MX answers from this file until their TTL expires, and save new ones to it
+    #[arg(long, conflicts_with = "dns_fixture", global = true)]
+    dns_cache: Option<PathBuf>,
+
+    /// Never query DNS; MX rules are answered from --dns-fixture or
+    /// --dns-cache, or reported as not evaluated
+    #[arg(
+        long,
+        conflicts_with = "nameserver",
+        overrides_with = "no_offline",
+        global = true
+    )]
+    offline: bool,
+
+    /// Query DNS even if a config file sets `offline`
+    #[arg(long, overrides_with = "offline", global = true)]
+    no_offline: bool,
+
+    /// Number of domains to resolve concurrently
+    #[arg(long, default_value_t = 8, global = true)]
+    dns_workers: usize,
+
+    /// Always exit with status 0, even if violations are found or the check fails
+    #[arg(long, overrides_with = "no_report_only", global = true)]
+    report_only: bool,
+
+    /// Exit with the usual status even if a config file sets `report-only`
+    #[arg(long, overrides_with = "report_only", global = true)]
+    no_report_only: bool,
+
+    /// Ignore the user and repository config files
+    #[arg(long, global = true)]
+    no_config: bool,
+
+    /// Print the options in effect, merged from config files and flags, and exit
+    #[arg(long)]
+    print_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Report invalid, duplicate, shadowed and never-matching rules
+    LintRules {
+        /// Rules files to lint, as one set; defaults to the --rules files
+        #[arg(value_name = "RULES")]
+        files: Vec<PathBuf>,
+    },
+    /// Show how every rule evaluates against one email address
+    Explain {
+        /// The email address to explain
+        email: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Output {
+    Text,
+    Github,
+    Json,
+    Sarif,
+}
+
+fn parse_nameserver(s: &str) -> Result<SocketAddr, String> {
+    s.parse()
+        .or_else(|_| s.parse().map(|ip: IpAddr| SocketAddr::new(ip, 53)))
+        .map_err(|_| format!("invalid nameserver address '{s}'"))
+}
+
+/// Exit status when at least one violation was found.
+const EXIT_VIOLATIONS: u8 = 1;
+/// Exit status when the check itself could not be completed.
+const EXIT_ERROR: u8 = 2;
+
+fn main() -> ExitCode {
+    let matches = Args::command().get_matches();
+    let (args, config_files) = match load_args(&matches) {
+        Result::Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            return ExitCode::from(EXIT_ERROR);
+        }
+    };
+    if args.print_config {
+        return match print_config(&args, &config_files) {
+            Result::Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Error: {e:#}");
+                ExitCode::from(EXIT_ERROR)
+            }
+        };
+    }
+    let rules = match &args.command {
+        Some(Command::LintRules { files }) if !files.is_empty() => files,
+        _ => &args.rules,
+    };
+    if rules.is_empty() {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                format!(
+                    "no rules file given with --rules or in {}",
+                    config::FILE_NAME
+                ),
+            )
+            .exit();
+    }
+    match &args.command {
+        Some(Command::LintRules { .. }) => return ExitCode::from(lint_rules(rules)),
+        Some(Command::Explain { email }) => {
+            return match explain(&args, email) {
+                Result::Ok(_) if args.report_only => ExitCode::SUCCESS,
+                Result::Ok(status) => ExitCode::from(status),
+                Err(e) => {
+                    eprintln!("Error: {e:#}");
+                    ExitCode::from(if args.report_only { 0 } else { EXIT_ERROR })
+                }
+            };
+        }
+        None => {}
+    }
 
-    /// Output format (text|github)
-    #[arg(short, long, default_value = "text")]
-    output: String,
+    let report_only = args.report_only;
+    let result = run(args);
+    if let Err(e) = &result {
+        eprintln!("Error: {e:#}");
+    }
+    ExitCode::from(exit_code(&result, report_only))
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    run(args)?;
+/// Parse the command line and fill in the options it leaves out from the
+/// config files, returning the files that were read.
+fn load_args(matches: &ArgMatches) -> Result<(Args, Vec<PathBuf>)> {
+    let mut args = Args::from_arg_matches(matches)?;
+    let files = if args.no_config {
+        Vec::new()
+    } else {
+        config::discover(args.repo.as_deref())
+    };
+    let mut config = Config::default();
+    for file in &files {
+        config = config.merge(Config::from_file(file)?);
+    }
+    config.apply(&mut args, matches)?;
+    Ok((args, files))
+}
+
+fn print_config(args: &Args, config_files: &[PathBuf]) -> Result<()> {
+    for file in config_files {
+        println!("# from {}", file.display());
+    }
+    print!("{}", toml::to_string(&Config::from_args(args))?);
     Ok(())
 }
 
-fn run(args: Args) -> Result<Vec<String>> {
-    let bad_rules = read_rules(&args.rules)?;
-    let commit_emails = read_emails(&args.emails)?;
+/// Print the problems with the rules files, returning the exit status.
+fn lint_rules(files: &[PathBuf]) -> u8 {
+    match lint::lint_files(files) {
+        Result::Ok(findings) if findings.is_empty() => {
+            println!("✅ No problems found in the rules");
+            0
+        }
+        Result::Ok(findings) => {
+            for finding in &findings {
+                println!("{finding}");
+            }
+            println!("❌ {} problem(s) found in the rules", findings.len());
+            EXIT_VIOLATIONS
+        }
+        Err(e) => {
+            eprintln!("Error: {e:#}");
+            EXIT_ERROR
+        }
+    }
+}
 
-    let regex_rules = compile_rules(bad_rules);
+fn exit_code(result: &Result<Report>, report_only: bool) -> u8 {
+    match result {
+        _ if report_only => 0,
+        Result::Ok(report) => check_status(
+            report.has_errors(),
+            !report.unverified.is_empty(),
+            report.dns_failure,
+        ),
+        Err(_) => EXIT_ERROR,
+    }
+}
+
+/// The exit status of a check that ran to completion: violations of `error`
+/// rules first, then lookups left unverified under [`DnsFailure::Unknown`].
+fn check_status(has_errors: bool, has_unverified: bool, dns_failure: DnsFailure) -> u8 {
+    if has_errors {
+        EXIT_VIOLATIONS
+    } else if has_unverified && dns_failure == DnsFailure::Unknown {
+        EXIT_ERROR
+    } else {
+        0
+    }
+}
 
-    let violations = find_violations(commit_emails, regex_rules);
+/// A checker for the rules with the DNS options applied, and the disk cache
+/// to save once it is done.
+fn checker(args: &Args, rules: RuleSet) -> Result<(Checker, Option<Arc<DiskCache>>)> {
+    let checker = Checker::new(rules)
+        .dns_failure(args.dns_failure)
+        .dns_workers(args.dns_workers);
+    let resolver: Box<dyn MxResolver> = match args.nameserver.as_slice() {
+        _ if args.offline => Box::new(OfflineResolver),
+        [] => Box::new(DnsResolver::system()),
+        nameservers => Box::new(DnsResolver::with_nameservers(nameservers)),
+    };
+    Ok(match (&args.dns_fixture, &args.dns_cache) {
+        (Some(fixture), _) => (checker.resolver(StaticResolver::from_file(fixture)?), None),
+        (None, Some(path)) => {
+            let cache = Arc::new(DiskCache::open(path, resolver)?);
+            (checker.resolver(cache.clone()), Some(cache))
+        }
+        (None, None) => (checker.resolver(resolver), None),
+    })
+}
 
-    match args.output.as_str() {
-        "github" => output_github(violations.iter().collect()),
-        _ => output_text(violations.iter().collect()),
+/// Print how every rule evaluates against `email`, returning the exit status
+/// a check of just that email would have.
+fn explain(args: &Args, email: &str) -> Result<u8> {
+    let (checker, dns_cache) = checker(args, RuleSet::from_files(&args.rules)?)?;
+    let explanation = checker.explain(email);
+    if let Some(cache) = dns_cache {
+        cache.save()?;
     }
+    print!("{}", render_explanation(email, &explanation));
+
+    let verdict = &explanation.verdict;
+    let has_errors = verdict
+        .violation
+        .as_ref()
+        .is_some_and(|v| v.metadata.severity == Severity::Error);
+    Ok(check_status(
+        has_errors,
+        !verdict.unverified.is_empty(),
+        args.dns_failure,
+    ))
+}
+
+fn render_explanation(email: &str, explanation: &Explanation) -> String {
+    let mut out = format!("{email} against {} rule(s):\n", explanation.rules.len());
+    for trace in &explanation.rules {
+        let CompiledRule { source, rule, .. } = trace.rule;
+        let location = match &source.file {
+            Some(file) => format!("{}:{}", file.display(), source.line),
+            None => format!("line {}", source.line),
+        };
+        let outcome = match &trace.result {
+            Result::Ok(None) => "no match".to_string(),
+            Result::Ok(Some(Match::Pattern)) => "matched".to_string(),
+            Result::Ok(Some(Match::MxHost(host))) => format!("matched MX {host}"),
+            Result::Ok(Some(Match::NoMail(reason))) => format!("matched, {reason}"),
+            Result::Ok(Some(Match::MxAddress { host, ip })) => {
+                format!("matched MX {host}, address {ip}")
+            }
+            Result::Ok(Some(Match::NsHost(host))) => format!("matched NS {host}"),
+            Err(e) if e.is::<Offline>() => "not evaluated (offline)".to_string(),
+            Err(e) => format!("could not be evaluated: {e:#}"),
+        };
+        out.push_str(&format!("\n{location}: `{}` — {outcome}\n", source.text));
+        if let Some(regex) = rule.regex() {
+            out.push_str(&format!("    regex: {regex}\n"));
+        }
+        for lookup in &trace.lookups {
+            let answer = match &lookup.answer {
+                Result::Ok(records) if records.is_empty() => "no records".to_string(),
+                Result::Ok(records) => records
+                    .iter()
+                    .map(|r| if r.is_empty() { "." } else { r })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                Err(e) => e.clone(),
+            };
+            out.push_str(&format!(
+                "    {} {}: {answer}\n",
+                lookup.kind, lookup.domain
+            ));
+        }
+    }
+
+    let verdict = &explanation.verdict;
+    out.push('\n');
+    if let Some(v) = &verdict.violation {
+        let mark = match v.metadata.severity {
+            Severity::Error => "❌",
+            Severity::Warning => "🔶",
+            Severity::Notice => "ℹ️",
+        };
+        out.push_str(&format!("{mark} {}\n", v.describe()));
+    }
+    if !verdict.unverified.is_empty() {
+        out.push_str(&format!(
+            "⚠️ {email} could not be verified against {} rule(s)\n",
+            verdict.unverified.len()
+        ));
+    }
+    if !verdict.not_evaluated.is_empty() {
+        out.push_str(&format!(
+            "⏭️ {email} was not checked against {} rule(s) (offline)\n",
+            verdict.not_evaluated.len()
+        ));
+    }
+    if verdict.violation.is_none()
+        && verdict.unverified.is_empty()
+        && verdict.not_evaluated.is_empty()
+    {
+        out.push_str(&format!("✅ {email} meets the requirements\n"));
+    }
+    out
+}
+
+fn run(args: Args) -> Result<Report> {
+    let rules = RuleSet::from_files(&args.rules)?;
+    let commit_emails = match (&args.repo, &args.emails) {
+        (Some(repo), _) => git::read_emails(repo, &args.range)?,
+        (None, Some(emails)) => read_emails(emails)?,
+        (None, None) => unreachable!("clap requires --emails or --repo"),
+    };
+
+    let (checker, dns_cache) = checker(&args, rules)?;
+    let report = checker.check(commit_emails);
+    if let Some(cache) = dns_cache {
+        cache.save()?;
+    }
+
+    match args.output {
+        Output::Text => output_text(&report),
+        Output::Github => output_github(&report)?,
+        Output::Json => output_json(&report)?,
+        Output::Sarif => output_sarif(&report, &args.rules[0])?,
+    }
+
+    Ok(report)
+}
+
+fn output_github(report: &Report) -> Result<()> {
+    for annotation in github_annotations(report) {
+        println!("{annotation}");
+    }
+
+    // outside of a workflow step, print the outputs for the caller to redirect
+    let outputs = github_outputs(report);
+    match env::var_os("GITHUB_OUTPUT") {
+        Some(path) => append(path, &outputs)?,
+        None => print!("{outputs}"),
+    }
+    if let Some(path) = env::var_os("GITHUB_STEP_SUMMARY") {
+        append(path, &github_step_summary(report))?;
+    }
+    Ok(())
+}
+
+fn append(path: impl AsRef<Path>, content: &str) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+/// `::error`, `::warning` or `::notice` workflow commands, by rule
+/// severity, one per offending commit.
+fn github_annotations(report: &Report) -> Vec<String> {
+    let mut annotations = Vec::new();
+    for v in &report.violations {
+        let command = v.metadata.severity.to_string();
+        let message = v.describe();
+        if v.origins.is_empty() {
+            annotations.push(workflow_command(
+                &command,
+                "Commit email violation",
+                &message,
+            ));
+        }
+        for origin in &v.origins {
+            let message = format!("{message} in {origin}");
+            annotations.push(workflow_command(
+                &command,
+                "Commit email violation",
+                &message,
+            ));
+        }
+    }
+    for u in &report.unverified {
+        let message = format!("{}: {}", u.describe(), u.error);
+        annotations.push(workflow_command(
+            "warning",
+            "Could not verify commit email",
+            &message,
+        ));
+    }
+    annotations
+}
+
+fn workflow_command(command: &str, title: &str, message: &str) -> String {
+    let escape_data = |s: &str| {
+        s.replace('%', "%25")
+            .replace('\r', "%0D")
+            .replace('\n', "%0A")
+    };
+    let title = escape_data(title).replace(':', "%3A").replace(',', "%2C");
+    format!("::{command} title={title}::{}", escape_data(message))
+}
+
+/// Step outputs in the multi-line `$GITHUB_OUTPUT` file format.
+fn github_outputs(report: &Report) -> String {
+    let mut outputs = String::new();
+    let mut output = |name: &str, value: &str| {
+        if value.contains('\n') {
+            let mut delimiter = format!("ghadelimiter_{}", process::id());
+            while value.contains(&delimiter) {
+                delimiter.push('_');
+            }
+            outputs.push_str(&format!("{name}<<{delimiter}\n{value}\n{delimiter}\n"));
+        } else {
+            outputs.push_str(&format!("{name}={value}\n"));
+        }
+    };
+
+    let violations = report
+        .violations
+        .iter()
+        .map(|v| format!("- {}", v.describe())) // Markdown lists
+        .collect::<Vec<_>>()
+        .join("\n");
+    output(
+        "has_violations",
+        &(!report.violations.is_empty()).to_string(),
+    );
+    output("violations", &violations);
+
+    let unverified = report
+        .unverified
+        .iter()
+        .map(|u| format!("- {}: {}", u.describe(), u.error))
+        .collect::<Vec<_>>()
+        .join("\n");
+    output(
+        "has_unverified",
+        &(!report.unverified.is_empty()).to_string(),
+    );
+    output("unverified", &unverified);
+
+    let not_evaluated = report
+        .not_evaluated
+        .iter()
+        .map(|s| format!("- {}", s.describe()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    output(
+        "has_not_evaluated",
+        &(!report.not_evaluated.is_empty()).to_string(),
+    );
+    output("not_evaluated", &not_evaluated);
+    outputs
+}
+
+/// Markdown tables for `$GITHUB_STEP_SUMMARY`.
+fn github_step_summary(report: &Report) -> String {
+    let cell = |s: &str| s.replace('|', "\\|");
+    let mut summary = String::from("## Commit email check\n\n");
+    if report.is_clean() {
+        summary.push_str("✅ All submitted email addresses meet the requirements\n");
+    } else if report.violations.is_empty() {
+        summary.push_str("⚠️ No violations found, but not every email address was fully checked\n");
+    }
+    if !report.violations.is_empty() {
+        summary.push_str(
+            "| Email | Severity | Rule | Location | Commits |\n| --- | --- | --- | --- | --- |\n",
+        );
+        for v in &report.violations {
+            let commits = v
+                .origins
+                .iter()
+                .map(|o| format!("`{o}`"))
+                .collect::<Vec<_>>()
+                .join("<br>");
+            summary.push_str(&format!(
+                "| {} | {} | `{}` | {} | {} |\n",
+                cell(&v.email),
+                v.metadata.severity,
+                cell(&v.rule),
+                v.location(),
+                cell(&commits)
+            ));
+        }
+    }
+    if !report.unverified.is_empty() {
+        summary.push_str("\n### Could not verify\n\n| Email | Rule | Location | Error |\n| --- | --- | --- | --- |\n");
+        for u in &report.unverified {
+            summary.push_str(&format!(
+                "| {} | `{}` | {} | {} |\n",
+                cell(&u.email),
+                cell(&u.rule),
+                u.location(),
+                cell(&u.error)
+            ));
+        }
+    }
+    if !report.not_evaluated.is_empty() {
+        summary.push_str(&format!(
+            "\n⏭️ {} rule check(s) not evaluated (offline)\n",
+            report.not_evaluated.len()
+        ));
+    }
+    summary
+}
 
-    Ok(violations)
+fn output_text(report: &Report) {
+    print!("{}", render_text(report));
 }
+
+fn render_text(report: &Report) -> String {
+    let Report {
+        unverified,
+        not_evaluated,
+        ..
+    } = report;
+    let mut out = String::new();
+    if report.is_clean() {
+        out.push_str("✅ All submitted email addresses meet the requirements\n");
+    } else if report.violations.is_empty() {
+        out.push_str("⚠️ No violations found, but not every email address was fully checked\n");
+    }
+    for severity in [Severity::Error, Severity::Warning, Severity::Notice] {
+        let violations: Vec<_> = report.violations_of(severity).collect();
+        if violations.is_empty() {
+            continue;
+        }
+        out.push_str(&match severity {
+            Severity::Error => format!(
+                "❌ {} violating email address(es) detected:\n",
+                violations.len()
+            ),
+            Severity::Warning => {
+                format!("🔶 {} email address(es) with warnings:\n", violations.len())
+            }
+            Severity::Notice => {
+                format!("ℹ️ {} email address(es) with notices:\n", violations.len())
+            }
+        });
+        for (i, v) in violations.iter().enumerate() {
+            let mut line = v.describe();
+            if !v.origins.is_empty() {
+                let origins: Vec<_> = v.origins.iter().map(ToString::to_string).collect();
+                line.push_str(&format!(" in {}", origins.join(", ")));
+            }
+            out.push_str(&format!("  {}. {line}\n", i + 1));
+        }
+    }
+    if !unverified.is_empty() {
+        out.push_str(&format!(
+            "⚠️ {} rule check(s) could not be verified:\n",
+            unverified.len()
+        ));
+        for (i, u) in unverified.iter().enumerate() {
+            out.push_str(&format!("  {}. {}: {}\n", i + 1, u.describe(), u.error));
+        }
+    }
+    if !not_evaluated.is_empty() {
+        out.push_str(&format!(
+            "⏭️ {} rule check(s) not evaluated (offline):\n",
+            not_evaluated.len()
+        ));
+        for (i, s) in not_evaluated.iter().enumerate() {
+            out.push_str(&format!("  {}. {}\n", i + 1, s.describe()));
+        }
+    }
+    out
+}
+
+fn render_json(report: &Report) -> serde_json::Value {
+    serde_json::json!({
+        "checked": report.checked,
+        "violations": report.violations,
+        "unverified": report.unverified,
+        "not_evaluated": report.not_evaluated,
+        "dns_failure": report.dns_failure,
+        "summary": {
+            "checked": report.checked.len(),
+            "violations": report.violations.len(),
+            "errors": report.violations_of(Severity::Error).count(),
+            "warnings": report.violations_of(Severity::Warning).count(),
+            "notices": report.violations_of(Severity::Notice).count(),
+            "unverified": report.unverified.len(),
+            "not_evaluated": report.not_evaluated.len(),
+        },
+    })
+}
+
+fn output_json(report: &Report) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&render_json(report))?);
+    Ok(())
+}
+
+fn output_sarif(report: &Report, rules_path: &Path) -> Result<()> {
+    let sarif = sarif::render(report, rules_path);
+    println!("{}", serde_json::to_string_pretty(&sarif)?);
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
-    use crate::{Args, run};
+    use crate::{
+        Args, EXIT_ERROR, EXIT_VIOLATIONS, checker, exit_code, explain, github_annotations,
+        github_outputs, github_step_summary, lint_rules, render_explanation, render_json,
+        render_text, run,
+    };
+    use check_commits_email::{RuleSet, Severity, git::Role, sarif};
+    use clap::Parser;
+    use std::{path::Path, process::Command};
+
+    fn args(argv: &[&str]) -> Args {
+        Args::parse_from(["check-commits"].iter().chain(argv))
+    }
 
     #[test]
     fn test_1() {
-        let arg = Args {
-            rules: "test-rules.txt".into(),
-            emails: "test-emails-1.txt".into(),
-            output: "text".into(),
-        };
-        let violations = run(arg).unwrap();
+        let arg = args(&["-r", "test-rules.txt", "-e", "test-emails-1.txt"]);
+        let violations = run(arg).unwrap().violations;
         assert_eq!(violations.len(), 1);
-        assert_eq!(violations.first().unwrap(), "abc@hotmail.com")
+        assert_eq!(violations.first().unwrap().email, "abc@hotmail.com");
+        assert_eq!(violations.first().unwrap().rule, "*@hotmail.com");
+        assert_eq!(violations.first().unwrap().line, 1);
     }
 
     #[test]
     fn test_2() {
-        let arg = Args {
-            rules: "test-rules.txt".into(),
-            emails: "test-emails-2.txt".into(),
-            output: "text".into(),
-        };
-        let violations = run(arg).unwrap();
+        let arg = args(&["-r", "test-rules.txt", "-e", "test-emails-2.txt"]);
+        let violations = run(arg).unwrap().violations;
         assert_eq!(violations.len(), 1);
-        assert_eq!(violations.first().unwrap(), "1245@foxmail.com")
+        assert_eq!(violations.first().unwrap().email, "1245@foxmail.com");
+        assert_eq!(violations.first().unwrap().line, 2);
     }
 
     #[test]
     fn test_3() {
-        let arg = Args {
-            rules: "test-rules.txt".into(),
-            emails: "test-emails-3.txt".into(),
-            output: "text".into(),
-        };
-        let violations = run(arg).unwrap();
+        let arg = args(&["-r", "test-rules.txt", "-e", "test-emails-3.txt"]);
+        let violations = run(arg).unwrap().violations;
         assert_eq!(violations.len(), 0);
     }
 
     #[test]
     fn test_4() {
-        let arg = Args {
-            rules: "test-mx-record.txt".into(),
-            emails: "test-emails-4.txt".into(),
-            output: "text".into(),
-        };
-        let violations = run(arg).unwrap();
+        let arg = args(&[
+            "-r",
+            "test-mx-record.txt",
+            "-e",
+            "test-emails-4.txt",
+            "--dns-fixture",
+            "test-mx-fixture.txt",
+        ]);
+        let violations = run(arg).unwrap().violations;
         assert_eq!(violations.len(), 1);
+        assert_eq!(violations.first().unwrap().line, 2);
+        assert_eq!(
+            violations.first().unwrap().mx_host.as_deref(),
+            Some("route1.mx.cloudflare.net")
+        );
     }
-}
 
-fn read_rules(path: impl AsRef<Path>) -> Result<HashSet<String>> {
-    Ok(fs::read_to_string(path)?
-        .lines()
-        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
-        .map(|s| s.to_string())
-        .collect())
-}
+    #[test]
+    fn test_exit_code() {
+        let found = run(args(&["-r", "test-rules.txt", "-e", "test-emails-1.txt"]));
+        let clean = run(args(&["-r", "test-rules.txt", "-e", "test-emails-3.txt"]));
+        let failed = run(args(&["-r", "missing.txt", "-e", "test-emails-1.txt"]));
+        assert_eq!(exit_code(&found, false), EXIT_VIOLATIONS);
+        assert_eq!(exit_code(&clean, false), 0);
+        assert_eq!(exit_code(&failed, false), EXIT_ERROR);
+        assert_eq!(exit_code(&found, true), 0);
+        assert_eq!(exit_code(&failed, true), 0);
+    }
 
-fn read_emails(path: impl AsRef<Path>) -> Result<HashSet<String>> {
-    Ok(fs::read_to_string(path)?
-        .lines()
-        .map(|s| s.to_string())
-        .collect())
-}
+    #[test]
+    fn test_multiple_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let extra = dir.path().join("extra.txt");
+        std::fs::write(&extra, "*@abcfff.com\n").unwrap();
+        let report = run(args(&[
+            "-r",
+            "test-rules.txt",
+            "-r",
+            extra.to_str().unwrap(),
+            "-e",
+            "test-emails-2.txt",
+        ]))
+        .unwrap();
+        assert_eq!(report.violations.len(), 2);
+        assert_eq!(report.violations[1].email, "23333@abcfff.com");
+        assert_eq!(report.violations[1].file.as_deref(), Some(extra.as_path()));
 
-enum Rule {
-    Regex(Regex),
-    MxRecord(String),
-}
+        let sarif = sarif::render(&report, Path::new("test-rules.txt"));
+        let rules = &sarif["runs"][0]["tool"]["driver"]["rules"];
+        assert_eq!(rules[0]["id"], "extra.txt-line-1");
+        assert_eq!(rules[1]["id"], "rules-line-2");
+        let results = &sarif["runs"][0]["results"];
+        let uri = |i: usize| {
+            results[i]["locations"][0]["physicalLocation"]["artifactLocation"]["uri"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(uri(0), "test-rules.txt");
+        assert!(uri(1).starts_with("file:///") && uri(1).ends_with("/extra.txt"));
+    }
 
-impl Rule {
-    fn is_match(&self, email: &str) -> Result<bool> {
-        static RESOLVER: LazyLock<Resolver> = LazyLock::new(|| {
-            Resolver::new(ResolverConfig::default(), ResolverOpts::default()).unwrap()
-        });
-        match self {
-            Rule::Regex(regex) => Ok(regex.is_match(email)),
-            Rule::MxRecord(record) => {
-                if let Some(host) = email.split('@').next_back() {
-                    Ok(RESOLVER.mx_lookup(host)?.into_iter().any(|v| {
-                        let mut str = v.exchange().to_ascii();
-                        if str.ends_with('.') {
-                            str.remove(str.len() - 1);
-                        }
-                        &str == record
-                    }))
-                } else {
-                    Ok(false)
-                }
-            }
-        }
+    #[test]
+    fn test_severity() {
+        let report = run(args(&["-r", "test-rules.toml", "-e", "test-emails-2.txt"]));
+        let violations = &report.as_ref().unwrap().violations;
+        assert_eq!(violations[0].metadata.severity, Severity::Warning);
+        assert_eq!(exit_code(&report, false), 0);
+
+        let annotations = github_annotations(report.as_ref().unwrap());
+        assert!(annotations[0].starts_with("::warning title=Commit email violation::1245@"));
+        let json = render_json(report.as_ref().unwrap());
+        assert_eq!(json["summary"]["warnings"], 1);
+        assert_eq!(json["summary"]["errors"], 0);
     }
-}
 
-fn compile_rules(bad_rules: HashSet<String>) -> Vec<Rule> {
-    bad_rules
-        .into_iter()
-        .filter_map(|rule| {
-            if rule.starts_with("MX-RECORD,") {
-                match rule.split(",").last() {
-                    Some(v) => Some(Rule::MxRecord(v.into())),
-                    None => {
-                        eprintln!("Invalid rule {rule}");
-                        None
-                    }
-                }
-            } else {
-                let pattern = rule.trim().replace(".", r"\.").replace("*", ".*");
-                Regex::new(&format!(r"(?i)^{}", pattern))
-                    .map_err(|e| eprintln!("Invalid rule '{}': {}", rule, e))
-                    .map(Rule::Regex)
-                    .ok()
-            }
-        })
-        .collect()
-}
+    #[test]
+    fn test_lint_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = dir.path().join("rules.txt");
+        std::fs::write(&rules, "*@gmail.com\n*@gmail.com\nMX-RECORD,\n").unwrap();
+        assert_eq!(lint_rules(&[rules]), EXIT_VIOLATIONS);
+        assert_eq!(lint_rules(&["test-rules.toml".into()]), 0);
+        assert_eq!(lint_rules(&["missing.txt".into()]), EXIT_ERROR);
 
-fn find_violations(commit_emails: HashSet<String>, regex_rules: Vec<Rule>) -> Vec<String> {
-    let mut violations: Vec<_> = commit_emails
-        .iter()
-        .filter(|email| {
-            regex_rules
-                .iter()
-                .any(|re| re.is_match(email).unwrap_or(false))
-        })
-        .cloned()
-        .collect();
+        let arg = args(&["-r", "test-rules.txt", "lint-rules"]);
+        assert!(arg.command.is_some());
+        let arg = args(&["lint-rules", "-r", "test-rules.txt"]);
+        assert_eq!(arg.rules, [Path::new("test-rules.txt")]);
+    }
 
-    violations.sort_unstable();
-    violations
-}
+    #[test]
+    fn test_explain() {
+        let arg = args(&[
+            "explain",
+            "absd@itsusinn.eu.org",
+            "-r",
+            "test-mx-record.txt",
+            "--dns-fixture",
+            "test-mx-fixture.txt",
+        ]);
+        let (checker, _) = checker(&arg, RuleSet::from_files(&arg.rules).unwrap()).unwrap();
+        let text = render_explanation(
+            "absd@itsusinn.eu.org",
+            &checker.explain("absd@itsusinn.eu.org"),
+        );
+        assert!(text.contains("test-mx-record.txt:1: `MX-RECORD,mxbiz1.qq.com` — no match\n"));
+        assert!(text.contains("    regex: (?i)^mxbiz1\\.qq\\.com$\n"));
+        assert!(text.contains("    MX itsusinn.eu.org: route1.mx.cloudflare.net, route2."));
+        assert!(text.contains("— matched MX route1.mx.cloudflare.net\n"));
+        assert!(text.ends_with("(test-mx-record.txt:2, MX route1.mx.cloudflare.net)\n"));
+    }
 
-fn output_github(violations: Vec<&String>) {
-    if violations.is_empty() {
-        println!("has_violations=false");
-    } else {
-        // convert to GitHub Actions format
-        let formatted = violations
-            .iter()
-            .map(|s| format!("• {}", s)) // Markdown lists
-            .collect::<Vec<_>>()
-            .join("%0A"); // Github multiline string
+    #[test]
+    fn test_explain_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = dir.path().join("rules.toml");
+        // an empty label makes the lookup fail without touching the network
+        std::fs::write(
+            &rules,
+            "[[rule]]\npattern = '*@bad..domain'\nseverity = 'warning'\n\n\
+             [[rule]]\nkind = 'MX-RECORD'\npattern = 'mx.example.com'\n",
+        )
+        .unwrap();
+        let rules = rules.to_str().unwrap();
+        let emails = dir.path().join("emails.txt");
+        std::fs::write(&emails, "a@bad..domain\n").unwrap();
+
+        let check = run(args(&["-r", rules, "-e", emails.to_str().unwrap()]));
+        assert_eq!(exit_code(&check, false), EXIT_ERROR);
+        let arg = args(&["explain", "a@bad..domain", "-r", rules]);
+        assert_eq!(explain(&arg, "a@bad..domain").unwrap(), EXIT_ERROR);
+
+        let (checker, _) = checker(&arg, RuleSet::from_files(&arg.rules).unwrap()).unwrap();
+        let text = render_explanation("a@bad..domain", &checker.explain("a@bad..domain"));
+        assert!(text.contains("\n🔶 a@bad..domain — rule `*@bad..domain`"));
+        assert!(text.ends_with("⚠️ a@bad..domain could not be verified against 1 rule(s)\n"));
 
-        println!("has_violations=true");
-        println!("violations={}", formatted);
+        let arg = args(&["explain", "a@bad..domain", "-r", rules, "--report-only"]);
+        assert!(arg.report_only);
     }
-}
 
-fn output_text(violations: Vec<&String>) {
-    if violations.is_empty() {
-        println!("✅ All submitted email addresses meet the requirements");
-    } else {
-        println!(
-            "❌ {} violating email address(es) detected:",
-            violations.len()
+    #[test]
+    fn test_allow_rules() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = dir.path().join("rules.txt");
+        let emails = dir.path().join("emails.txt");
+        std::fs::write(
+            &rules,
+            "*\n!*@ourcorp.com\n!*@users.noreply.github.com\n*@gmail.com\n! release-bot@gmail.com\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &emails,
+            "dev@ourcorp.com\n1+dev@users.noreply.github.com\nrelease-bot@gmail.com\nme@gmail.com\nme@hotmail.com\n",
+        )
+        .unwrap();
+
+        let violations = run(args(&[
+            "-r",
+            rules.to_str().unwrap(),
+            "-e",
+            emails.to_str().unwrap(),
+        ]))
+        .unwrap()
+        .violations;
+        let emails: Vec<_> = violations.iter().map(|v| v.email.as_str()).collect();
+        assert_eq!(emails, ["me@gmail.com", "me@hotmail.com"]);
+        assert!(violations.iter().all(|v| v.line == 1));
+    }
+
+    #[test]
+    fn test_dns_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let rules = dir.path().join("rules.txt");
+        let emails = dir.path().join("emails.txt");
+        // an empty label makes the lookup fail without touching the network
+        std::fs::write(&rules, "MX-RECORD,mx.example.com\n").unwrap();
+        std::fs::write(&emails, "a@bad..domain\n").unwrap();
+        let (rules, emails) = (rules.to_str().unwrap(), emails.to_str().unwrap());
+
+        let check = |policy| run(args(&["-r", rules, "-e", emails, "--dns-failure", policy]));
+        let closed = check("closed");
+        let open = check("open");
+        let unknown = check("unknown");
+
+        let report = closed.as_ref().unwrap();
+        assert_eq!(report.violations.len(), 1);
+        assert!(report.violations[0].lookup_error.is_some());
+        assert_eq!(report.unverified.len(), 1);
+        assert_eq!(exit_code(&closed, false), EXIT_VIOLATIONS);
+
+        let report = open.as_ref().unwrap();
+        assert!(report.violations.is_empty());
+        assert_eq!(report.unverified.len(), 1);
+        assert_eq!(exit_code(&open, false), 0);
+
+        let report = unknown.as_ref().unwrap();
+        assert!(report.violations.is_empty());
+        assert_eq!(report.unverified[0].email, "a@bad..domain");
+        assert!(
+            report.unverified[0]
+                .describe()
+                .ends_with(&format!("({rules}:1)"))
+        );
+        assert_eq!(exit_code(&unknown, false), EXIT_ERROR);
+        assert!(!render_text(report).contains('✅'));
+        assert!(!github_step_summary(report).contains('✅'));
+    }
+
+    #[test]
+    fn test_json() {
+        let report = run(args(&[
+            "-r",
+            "test-rules.txt",
+            "-e",
+            "test-emails-1.txt",
+            "-o",
+            "json",
+        ]))
+        .unwrap();
+        let json = render_json(&report);
+        assert_eq!(json["checked"].as_array().unwrap().len(), 2);
+        assert_eq!(json["violations"][0]["email"], "abc@hotmail.com");
+        assert_eq!(json["violations"][0]["rule"], "*@hotmail.com");
+        assert_eq!(json["violations"][0]["line"], 1);
+        assert_eq!(json["summary"]["violations"], 1);
+        assert_eq!(json["summary"]["unverified"], 0);
+        assert_eq!(json["dns_failure"], "unknown");
+
+        let unknown = [
+            "check-commits",
+            "-r",
+            "test-rules.txt",
+            "-e",
+            "x",
+            "-o",
+            "xml",
+        ];
+        assert!(Args::try_parse_from(unknown).is_err());
+    }
+
+    #[test]
+    fn test_toml_rules() {
+        let report = run(args(&["-r", "test-rules.toml", "-e", "test-emails-1.txt"])).unwrap();
+        let violation = &report.violations[0];
+        assert_eq!(violation.rule, "*@hotmail.com");
+        assert_eq!(violation.line, 2);
+        assert_eq!(violation.metadata.id.as_deref(), Some("hotmail"));
+        assert_eq!(
+            violation.describe(),
+            "abc@hotmail.com — rule `*@hotmail.com` (test-rules.toml:2): \
+             Use your company address (https://example.com/commit-email)"
+        );
+
+        let json = render_json(&report);
+        assert_eq!(json["violations"][0]["id"], "hotmail");
+        assert_eq!(json["violations"][0]["severity"], "error");
+    }
+
+    #[test]
+    fn test_nameserver() {
+        let arg = args(&[
+            "-r",
+            "x",
+            "-e",
+            "x",
+            "--nameserver",
+            "1.1.1.1",
+            "--nameserver",
+            "[::1]:5353",
+        ]);
+        assert_eq!(arg.nameserver[0].to_string(), "1.1.1.1:53");
+        assert_eq!(arg.nameserver[1].to_string(), "[::1]:5353");
+        let argv = [
+            "check-commits",
+            "-r",
+            "x",
+            "-e",
+            "x",
+            "--nameserver",
+            "localhost",
+        ];
+        assert!(Args::try_parse_from(argv).is_err());
+    }
+
+    #[test]
+    fn test_offline() {
+        let base = [
+            "-r",
+            "test-mx-record.txt",
+            "-e",
+            "test-emails-4.txt",
+            "--offline",
+        ];
+        let report = run(args(&base)).unwrap();
+        assert!(report.violations.is_empty());
+        assert!(report.unverified.is_empty());
+        assert_eq!(report.not_evaluated.len(), 2);
+        let text = render_text(&report);
+        assert!(text.starts_with("⚠️ No violations found, but not every email address"));
+        assert!(text.contains("⏭️ 2 rule check(s) not evaluated (offline):\n"));
+        assert!(!github_step_summary(&report).contains('✅'));
+        let outputs = github_outputs(&report);
+        assert!(outputs.contains("has_not_evaluated=true\nnot_evaluated<<"));
+        assert_eq!(outputs.matches("\n- ").count(), 2);
+        assert_eq!(exit_code(&Ok(report), false), 0);
+
+        let fixture = args(&[&base[..], &["--dns-fixture", "test-mx-fixture.txt"]].concat());
+        assert_eq!(run(fixture).unwrap().violations.len(), 1);
+
+        let argv = [&["check-commits"], &base[..], &["--nameserver", "1.1.1.1"]].concat();
+        assert!(Args::try_parse_from(argv).is_err());
+    }
+
+    fn git(repo: &Path, argv: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(argv)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    fn commit(repo: &Path, author: &str, committer: &str) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["commit", "--allow-empty", "-q", "-m", "test"])
+            .args(["--author", &format!("Test <{author}>")])
+            .env("GIT_COMMITTER_NAME", "Test")
+            .env("GIT_COMMITTER_EMAIL", committer)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// A repository whose `base..HEAD` range has one commit by
+    /// abc@hotmail.com and an annotated tag by 1245@foxmail.com.
+    fn fixture_repo() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path();
+        git(repo, &["init", "-q"]);
+        commit(repo, "old@hotmail.com", "old@hotmail.com");
+        git(repo, &["tag", "base"]);
+        commit(repo, "abc@hotmail.com", "ci@example.com");
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(repo)
+            .args(["tag", "-a", "v1", "-m", "release"])
+            .env("GIT_COMMITTER_NAME", "Tagger")
+            .env("GIT_COMMITTER_EMAIL", "1245@foxmail.com")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        dir
+    }
+
+    #[test]
+    fn test_git_range() {
+        let dir = fixture_repo();
+        let repo = dir.path().to_str().unwrap();
+        let arg = args(&[
+            "-r",
+            "test-rules.txt",
+            "--repo",
+            repo,
+            "--range",
+            "base..HEAD",
+        ]);
+        let report = run(arg).unwrap();
+        let violations = &report.violations;
+        let emails: Vec<_> = violations.iter().map(|v| v.email.as_str()).collect();
+        assert_eq!(emails, ["1245@foxmail.com", "abc@hotmail.com"]);
+        assert_eq!(violations[0].origins[0].role, Role::Tagger);
+        assert_eq!(violations[0].origins[0].tag.as_deref(), Some("v1"));
+        assert_eq!(violations[1].origins.len(), 1);
+        assert_eq!(violations[1].origins[0].role, Role::Author);
+
+        let text = render_text(&report);
+        let author = format!(" in {}\n", violations[1].origins[0]);
+        assert!(text.contains(&format!("(test-rules.txt:1){author}")));
+    }
+
+    #[test]
+    fn test_github() {
+        let dir = fixture_repo();
+        let repo = dir.path().to_str().unwrap();
+        let arg = args(&[
+            "-r",
+            "test-rules.txt",
+            "--repo",
+            repo,
+            "--range",
+            "base..HEAD",
+        ]);
+        let report = run(arg).unwrap();
+
+        let annotations = github_annotations(&report);
+        assert_eq!(annotations.len(), 2);
+        assert!(
+            annotations[0].starts_with("::error title=Commit email violation::1245@foxmail.com")
+        );
+        assert!(annotations[0].ends_with(", tagger)"));
+
+        let outputs = github_outputs(&report);
+        let mut lines = outputs.lines();
+        assert_eq!(lines.next(), Some("has_violations=true"));
+        let delimiter = lines.next().unwrap().strip_prefix("violations<<").unwrap();
+        assert!(lines.next().unwrap().starts_with("- 1245@foxmail.com"));
+        assert!(lines.next().unwrap().starts_with("- abc@hotmail.com"));
+        assert_eq!(lines.next(), Some(delimiter));
+        assert_eq!(lines.next(), Some("has_unverified=false"));
+        assert!(outputs.contains("has_not_evaluated=false\n"));
+
+        let summary = github_step_summary(&report);
+        assert!(
+            summary.contains("| abc@hotmail.com | error | `*@hotmail.com` | test-rules.txt:1 | `")
+        );
+    }
+
+    #[test]
+    fn test_sarif() {
+        let dir = fixture_repo();
+        let repo = dir.path().to_str().unwrap();
+        let arg = args(&[
+            "-r",
+            "test-rules.txt",
+            "--repo",
+            repo,
+            "--range",
+            "base..HEAD",
+        ]);
+        let report = run(arg).unwrap();
+
+        let sarif = sarif::render(&report, Path::new("test-rules.txt"));
+        let run = &sarif["runs"][0];
+        let rules = run["tool"]["driver"]["rules"].as_array().unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0]["id"], "rules-line-1");
+        assert_eq!(rules[0]["name"], "*@hotmail.com");
+
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 2);
+        let tagger = &results[0];
+        assert_eq!(tagger["ruleId"], "rules-line-2");
+        assert_eq!(tagger["ruleIndex"], 1);
+        let location = &tagger["locations"][0];
+        assert_eq!(location["physicalLocation"]["region"]["startLine"], 2);
+        assert_eq!(location["logicalLocations"][0]["kind"], "commit");
+        assert_eq!(
+            location["logicalLocations"][0]["fullyQualifiedName"]
+                .as_str()
+                .unwrap()
+                .len(),
+            40
         );
-        for (i, email) in violations.iter().enumerate() {
-            println!("  {}. {}", i + 1, email);
-        }
     }
 }
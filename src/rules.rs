@@ -0,0 +1,912 @@
+use crate::{
+    cidr::Cidr,
+    dns::{MxResolver, NxDomain, RecordType},
+    glob,
+};
+use anyhow::{Context, Ok, Result, anyhow, bail};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, hash_map},
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// A rule as written in the rules file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleSource {
+    /// The rules file, unless the rules were parsed from a string
+    pub file: Option<PathBuf>,
+    /// 1-based line number in the rules file
+    pub line: usize,
+    pub text: String,
+}
+
+/// A compiled set of rules, in rules file order.
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    /// Read a rules file, as TOML if its extension is `.toml` and as plain
+    /// text otherwise.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_files([path])
+    }
+
+    /// Read several rules files as one set, in order, following their
+    /// includes. A file included more than once is only read the first time.
+    pub fn from_files<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Result<Self> {
+        let mut loader = Loader::default();
+        let mut rules = Vec::new();
+        for path in paths {
+            rules.extend(loader.load(path.as_ref())?);
+        }
+        Ok(RuleSet { rules })
+    }
+
+    /// Compile a TOML rules file: an optional `include` list of other rules
+    /// files, and an array of `[[rule]]` tables.
+    ///
+    /// ```toml
+    /// include = ["org-rules.toml"]
+    ///
+    /// [[rule]]
+    /// id = "personal-mail"
+    /// kind = "DOMAIN"
+    /// pattern = "gmail.com"
+    /// message = "Use your @corp.com address"
+    /// severity = "warning"
+    /// url = "https://wiki.corp.com/commit-email"
+    /// ```
+    ///
+    /// Includes need a file to resolve against; use
+    /// [`from_file`](Self::from_file) for those.
+    pub fn from_toml(s: &str) -> Result<Self> {
+        RuleSet::compile(toml_entries(s, None, &mut RuleIds::default())?)
+    }
+
+    fn compile(entries: Vec<Entry>) -> Result<Self> {
+        let rules = compile_rules(entries, |_| {
+            bail!("includes are only allowed in rules files")
+        })?;
+        Ok(RuleSet { rules })
+    }
+
+    pub fn rules(&self) -> &[CompiledRule] {
+        &self.rules
+    }
+}
+
+impl FromStr for RuleSet {
+    type Err = anyhow::Error;
+
+    /// Compile every rule, failing with all compile errors at once.
+    fn from_str(s: &str) -> Result<Self> {
+        RuleSet::compile(text_entries(s, None))
+    }
+}
+
+/// Reads rules files and the files they include, refusing include cycles.
+#[derive(Default)]
+struct Loader {
+    /// The files being read, outermost first, as canonical and given paths
+    stack: Vec<(PathBuf, PathBuf)>,
+    loaded: HashSet<PathBuf>,
+    ids: RuleIds,
+}
+
+impl Loader {
+    fn load(&mut self, path: &Path) -> Result<Vec<CompiledRule>> {
+        let canonical = fs::canonicalize(path)
+            .with_context(|| format!("failed to read rules file {}", path.display()))?;
+        if self.stack.iter().any(|(c, _)| *c == canonical) {
+            let chain: Vec<_> = self
+                .stack
+                .iter()
+                .map(|(_, p)| p.display().to_string())
+                .chain([path.display().to_string()])
+                .collect();
+            bail!("include cycle: {}", chain.join(" -> "));
+        }
+        if !self.loaded.insert(canonical.clone()) {
+            return Ok(Vec::new());
+        }
+
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read rules file {}", path.display()))?;
+        let entries = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml_entries(&text, Some(path), &mut self.ids)
+        } else {
+            Ok(text_entries(&text, Some(path)))
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        self.stack.push((canonical, path.to_path_buf()));
+        let rules = entries
+            .and_then(|entries| compile_rules(entries, |include| self.load(&dir.join(include))));
+        self.stack.pop();
+        rules.with_context(|| format!("invalid rules file {}", path.display()))
+    }
+}
+
+/// A line of a rules file, before includes are resolved.
+pub(crate) enum Entry {
+    Rule {
+        source: RuleSource,
+        metadata: Metadata,
+        compiled: Result<(bool, Rule)>,
+    },
+    /// Another rules file, relative to this one, whose rules go here
+    Include { source: RuleSource, path: String },
+}
+
+/// The entries of a plain rules file, dropping repeated lines.
+pub(crate) fn text_entries(text: &str, file: Option<&Path>) -> Vec<Entry> {
+    let mut seen = HashSet::new();
+    read_rules(text)
+        .into_iter()
+        .filter(|source| seen.insert(source.text.clone()))
+        .map(|mut source| {
+            source.file = file.map(Path::to_path_buf);
+            match source.text.trim().strip_prefix("include ") {
+                Some(path) => Entry::Include {
+                    path: path.trim().to_string(),
+                    source,
+                },
+                None => Entry::Rule {
+                    compiled: compile_rule(&source.text),
+                    metadata: Metadata::default(),
+                    source,
+                },
+            }
+        })
+        .collect()
+}
+
+/// The rule ids seen so far, with where each was first given, so an id is
+/// unique across every file in a rule set and not just within one.
+pub(crate) type RuleIds = HashMap<String, String>;
+
+pub(crate) fn toml_entries(s: &str, file: Option<&Path>, ids: &mut RuleIds) -> Result<Vec<Entry>> {
+    let toml: TomlRules = toml::from_str(s)?;
+    let line = |span: std::ops::Range<usize>| s[..span.start].matches('\n').count() + 1;
+    let source = |line, text| RuleSource {
+        file: file.map(Path::to_path_buf),
+        line,
+        text,
+    };
+
+    let mut entries: Vec<_> = toml
+        .include
+        .into_iter()
+        .map(|path| Entry::Include {
+            source: source(line(path.span()), format!("include {}", path.get_ref())),
+            path: path.into_inner(),
+        })
+        .collect();
+    for spanned in toml.rule {
+        let line = line(spanned.span());
+        let rule = spanned.into_inner();
+        let kind = rule.kind.to_ascii_uppercase();
+        let text = match kind.as_str() {
+            "GLOB" => rule.pattern.clone(),
+            "NO-MAIL" => kind.clone(),
+            _ => format!("{kind},{}", rule.pattern),
+        };
+        let text = if rule.allow { format!("!{text}") } else { text };
+        let source = source(line, text);
+        let compiled = match &rule.id {
+            Some(id) => match ids.entry(id.clone()) {
+                hash_map::Entry::Occupied(first) => Err(anyhow!(
+                    "duplicate rule id '{id}', first used at {}",
+                    first.get()
+                )),
+                hash_map::Entry::Vacant(slot) => {
+                    slot.insert(match file {
+                        Some(file) => format!("{}:{line}", file.display()),
+                        None => format!("line {line}"),
+                    });
+                    compile_kind(&kind, &rule.pattern).map(|r| (rule.allow, r))
+                }
+            },
+            None => compile_kind(&kind, &rule.pattern).map(|r| (rule.allow, r)),
+        };
+        entries.push(Entry::Rule {
+            source,
+            metadata: Metadata {
+                id: rule.id,
+                message: rule.message,
+                severity: rule.severity,
+                url: rule.url,
+            },
+            compiled,
+        });
+    }
+    Ok(entries)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlRules {
+    #[serde(default)]
+    include: Vec<toml::Spanned<String>>,
+    #[serde(default)]
+    rule: Vec<toml::Spanned<TomlRule>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlRule {
+    id: Option<String>,
+    #[serde(default = "glob_kind")]
+    kind: String,
+    #[serde(default)]
+    pattern: String,
+    #[serde(default)]
+    allow: bool,
+    message: Option<String>,
+    #[serde(default)]
+    severity: Severity,
+    url: Option<String>,
+}
+
+fn glob_kind() -> String {
+    "GLOB".to_string()
+}
+
+/// How serious a violation of a rule is.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Error,
+    Warning,
+    Notice,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Notice => "notice",
+        })
+    }
+}
+
+/// What a TOML rules file can say about a rule besides its pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Metadata {
+    pub id: Option<String>,
+    /// Shown alongside violations, e.g. "Use your @corp.com address"
+    pub message: Option<String>,
+    pub severity: Severity,
+    /// Documentation link for the rule
+    pub url: Option<String>,
+}
+
+/// The rule lines of a plain rules file, skipping comments and blank lines.
+pub(crate) fn read_rules(text: &str) -> Vec<RuleSource> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with('#') && !line.trim().is_empty())
+        .map(|(i, s)| RuleSource {
+            file: None,
+            line: i + 1,
+            text: s.to_string(),
+        })
+        .collect()
+}
+
+pub enum Rule {
+    Regex(Regex),
+    /// Emails whose domain has an MX host matching this wildcard pattern
+    MxRecord(Regex),
+    /// Emails whose domain has an MX host at or below this domain
+    MxSuffix(String),
+    /// Emails whose domain has an MX host with an address in this block
+    MxIp(Cidr),
+    /// Emails whose domain has a nameserver matching this wildcard pattern
+    NsRecord(Regex),
+    /// Emails at exactly this domain
+    Domain(String),
+    /// Emails at this domain or any of its subdomains
+    DomainSuffix(String),
+    /// Emails whose domain cannot receive mail: it does not exist, has a
+    /// null MX, or has neither MX nor address records
+    NoMail,
+}
+
+/// Why a rule matched an email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Match {
+    Pattern,
+    /// The MX host of the email's domain that matched the rule
+    MxHost(String),
+    /// Why the email's domain cannot receive mail
+    NoMail(String),
+    /// The MX host of the email's domain, and its address that matched
+    MxAddress {
+        host: String,
+        ip: String,
+    },
+    /// The nameserver of the email's domain that matched the rule
+    NsHost(String),
+}
+
+impl Rule {
+    /// The record types the rule looks up for every email's domain.
+    pub fn lookups(&self) -> &'static [RecordType] {
+        match self {
+            Rule::MxRecord(_) | Rule::MxSuffix(_) => &[RecordType::Mx],
+            Rule::MxIp(_) | Rule::NoMail => &[RecordType::Mx, RecordType::Address],
+            Rule::NsRecord(_) => &[RecordType::Ns],
+            Rule::Regex(_) | Rule::Domain(_) | Rule::DomainSuffix(_) => &[],
+        }
+    }
+
+    /// The regular expression the rule's pattern compiled to, if it has one.
+    pub fn regex(&self) -> Option<&Regex> {
+        match self {
+            Rule::Regex(regex) | Rule::MxRecord(regex) | Rule::NsRecord(regex) => Some(regex),
+            _ => None,
+        }
+    }
+
+    pub fn find_match(&self, email: &str, resolver: &dyn MxResolver) -> Result<Option<Match>> {
+        match self {
+            Rule::Regex(regex) => Ok(regex.is_match(email).then_some(Match::Pattern)),
+            Rule::Domain(domain) => {
+                Ok((normalize_domain(domain_of(email)) == *domain).then_some(Match::Pattern))
+            }
+            Rule::DomainSuffix(suffix) => {
+                let domain = normalize_domain(domain_of(email));
+                Ok(is_within(&domain, suffix).then_some(Match::Pattern))
+            }
+            Rule::MxRecord(pattern) => Ok(mail_hosts(domain_of(email), resolver)?
+                .hosts()
+                .find(|mx| pattern.is_match(mx))
+                .map(Match::MxHost)),
+            Rule::MxSuffix(suffix) => Ok(mail_hosts(domain_of(email), resolver)?
+                .hosts()
+                .find(|mx| is_within(mx, suffix))
+                .map(Match::MxHost)),
+            Rule::MxIp(cidr) => {
+                // a host whose addresses can't be looked up only matters
+                // if no other host matches
+                let mut error = None;
+                for host in mail_hosts(domain_of(email), resolver)?.hosts() {
+                    let ips = match resolver.lookup(&host, RecordType::Address) {
+                        Result::Ok(ips) => ips,
+                        Err(e) if e.is::<NxDomain>() => continue,
+                        Err(e) => {
+                            error.get_or_insert(e);
+                            continue;
+                        }
+                    };
+                    let ip = ips
+                        .into_iter()
+                        .find(|ip| ip.parse().is_ok_and(|ip| cidr.contains(&ip)));
+                    if let Some(ip) = ip {
+                        return Ok(Some(Match::MxAddress { host, ip }));
+                    }
+                }
+                match error {
+                    Some(e) => Err(e),
+                    None => Ok(None),
+                }
+            }
+            Rule::NsRecord(pattern) => Ok(ns_hosts(domain_of(email), resolver)?
+                .into_iter()
+                .find(|ns| pattern.is_match(ns))
+                .map(Match::NsHost)),
+            Rule::NoMail => Ok(match mail_hosts(domain_of(email), resolver)? {
+                MailHosts::Hosts(_) => None,
+                MailHosts::NullMx => Some(Match::NoMail("null MX".to_string())),
+                MailHosts::NxDomain => Some(Match::NoMail("domain does not exist".to_string())),
+                MailHosts::None => Some(Match::NoMail("no MX or address records".to_string())),
+            }),
+        }
+    }
+}
+
+/// Where mail for a domain is delivered.
+enum MailHosts {
+    Hosts(Vec<String>),
+    /// The domain declares that it accepts no mail (RFC 7505)
+    NullMx,
+    /// The domain does not exist
+    NxDomain,
+    /// The domain has neither MX nor address records
+    None,
+}
+
+impl MailHosts {
+    fn hosts(self) -> impl Iterator<Item = String> {
+        match self {
+            MailHosts::Hosts(hosts) => hosts.into_iter(),
+            MailHosts::NullMx | MailHosts::NxDomain | MailHosts::None => Vec::new().into_iter(),
+        }
+    }
+}
+
+/// The MX hosts of `domain`, falling back to the domain itself when it has
+/// no MX records but has an address (RFC 5321, section 5.1).
+fn mail_hosts(domain: &str, resolver: &dyn MxResolver) -> Result<MailHosts> {
+    let hosts = match resolver.mx_hosts(domain) {
+        Err(e) if e.is::<NxDomain>() => return Ok(MailHosts::NxDomain),
+        hosts => hosts?,
+    };
+    if hosts.iter().any(String::is_empty) {
+        return Ok(MailHosts::NullMx);
+    }
+    if !hosts.is_empty() {
+        return Ok(MailHosts::Hosts(hosts));
+    }
+    match resolver.lookup(domain, RecordType::Address) {
+        Err(e) if e.is::<NxDomain>() => return Ok(MailHosts::NxDomain),
+        Result::Ok(ips) if ips.is_empty() => return Ok(MailHosts::None),
+        ips => ips?,
+    };
+    Ok(MailHosts::Hosts(vec![normalize_domain(domain)]))
+}
+
+/// The nameservers of the zone `domain` belongs to: those of the domain
+/// itself, or else of its closest parent that has any, short of the TLD.
+fn ns_hosts(domain: &str, resolver: &dyn MxResolver) -> Result<Vec<String>> {
+    let domain = normalize_domain(domain);
+    let mut zone = domain.as_str();
+    loop {
+        let hosts = match resolver.lookup(zone, RecordType::Ns) {
+            Err(e) if e.is::<NxDomain>() => Vec::new(),
+            hosts => hosts?,
+        };
+        match zone.split_once('.') {
+            Some((_, parent)) if hosts.is_empty() && parent.contains('.') => zone = parent,
+            _ => return Ok(hosts),
+        }
+    }
+}
+
+/// The part of an email after the last `@`.
+pub(crate) fn domain_of(email: &str) -> &str {
+    email.rsplit('@').next().unwrap_or(email)
+}
+
+/// Whether `domain` is `parent` or one of its subdomains.
+pub(crate) fn is_within(domain: &str, parent: &str) -> bool {
+    domain
+        .strip_suffix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+}
+
+/// Lowercase a domain and strip its trailing dot.
+pub(crate) fn normalize_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_lowercase()
+}
+
+pub struct CompiledRule {
+    pub source: RuleSource,
+    pub rule: Rule,
+    /// Allow rules (`!` prefix) exempt an email from every deny rule
+    pub allow: bool,
+    pub metadata: Metadata,
+}
+
+/// A rule that failed to compile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleError {
+    /// 1-based line number in the rules file
+    pub line: usize,
+    pub rule: String,
+    pub message: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: invalid rule '{}': {}",
+            self.line, self.rule, self.message
+        )
+    }
+}
+
+/// Compile every rule, reading includes through `include`, and fail with
+/// all compile errors at once.
+fn compile_rules(
+    entries: Vec<Entry>,
+    mut include: impl FnMut(&str) -> Result<Vec<CompiledRule>>,
+) -> Result<Vec<CompiledRule>> {
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for entry in entries {
+        let (source, error) = match entry {
+            Entry::Rule {
+                source,
+                metadata,
+                compiled: Result::Ok((allow, rule)),
+            } => {
+                rules.push(CompiledRule {
+                    source,
+                    rule,
+                    allow,
+                    metadata,
+                });
+                continue;
+            }
+            Entry::Rule {
+                source,
+                compiled: Err(e),
+                ..
+            } => (source, e),
+            Entry::Include { source, path } => match include(&path) {
+                Result::Ok(included) => {
+                    rules.extend(included);
+                    continue;
+                }
+                Err(e) => (source, e),
+            },
+        };
+        errors.push(RuleError {
+            line: source.line,
+            rule: source.text,
+            message: format!("{error:#}"),
+        });
+    }
+    if !errors.is_empty() {
+        let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+        bail!("{}", errors.join("\n"));
+    }
+    Ok(rules)
+}
+
+fn domain_rule(domain: &str) -> Result<String> {
+    let domain = normalize_domain(domain);
+    if domain.is_empty() || domain.contains('@') {
+        bail!("expected a domain name");
+    }
+    Ok(domain)
+}
+
+/// Rule kinds written as `KIND,pattern` in plain rules files.
+const KINDS: &[&str] = &[
+    "MX-RECORD",
+    "MX-SUFFIX",
+    "MX-IP",
+    "NS-RECORD",
+    "DOMAIN",
+    "DOMAIN-SUFFIX",
+    "REGEX",
+];
+
+/// Split a rule as written into whether it is an allow rule, its kind (e.g.
+/// `MX-RECORD`, or `GLOB` for plain rules) and its pattern.
+pub(crate) fn parse_rule(text: &str) -> (bool, &str, &str) {
+    let text = text.trim();
+    let (allow, rule) = match text.strip_prefix('!') {
+        Some(rule) => (true, rule.trim_start()),
+        None => (false, text),
+    };
+    match rule.split_once(',') {
+        _ if rule == "NO-MAIL" => (allow, rule, ""),
+        Some((kind, pattern)) if KINDS.contains(&kind) => (allow, kind, pattern),
+        _ => (allow, "GLOB", rule),
+    }
+}
+
+fn compile_rule(text: &str) -> Result<(bool, Rule)> {
+    let (allow, kind, pattern) = parse_rule(text);
+    Ok((allow, compile_kind(kind, pattern)?))
+}
+
+/// Compile the pattern of a rule of the given kind, e.g. `MX-RECORD`.
+fn compile_kind(kind: &str, pattern: &str) -> Result<Rule> {
+    let empty = pattern.trim().is_empty();
+    Ok(match kind {
+        "MX-RECORD" | "MX-SUFFIX" | "MX-IP" if empty => bail!("empty MX target"),
+        "GLOB" | "REGEX" | "NS-RECORD" | "DOMAIN" | "DOMAIN-SUFFIX" if empty => {
+            bail!("empty pattern")
+        }
+        "GLOB" => Rule::Regex(glob::compile(pattern)?),
+        "REGEX" => Rule::Regex(Regex::new(pattern)?),
+        "MX-RECORD" => Rule::MxRecord(glob::compile(&normalize_domain(pattern))?),
+        "MX-SUFFIX" => Rule::MxSuffix(domain_rule(pattern)?),
+        "MX-IP" => Rule::MxIp(pattern.parse()?),
+        "NS-RECORD" => Rule::NsRecord(glob::compile(&normalize_domain(pattern))?),
+        "DOMAIN" => Rule::Domain(domain_rule(pattern)?),
+        "DOMAIN-SUFFIX" => Rule::DomainSuffix(domain_rule(pattern)?),
+        "NO-MAIL" if pattern.is_empty() => Rule::NoMail,
+        "NO-MAIL" => bail!("NO-MAIL rules take no pattern"),
+        _ => bail!("unknown rule kind '{kind}'"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Match, Rule, RuleSet, Severity};
+    use crate::dns::{MxResolver, OfflineResolver, RecordType, StaticResolver};
+    use anyhow::{Result, bail};
+    use std::fs;
+
+    #[test]
+    fn test_regex_rule() {
+        let rules: RuleSet = r"REGEX,^\d+@(qq|foxmail)\.com$".parse().unwrap();
+        let regex = |email| match &rules.rules()[0].rule {
+            super::Rule::Regex(regex) => regex.is_match(email),
+            _ => unreachable!(),
+        };
+        assert!(regex("12345@qq.com"));
+        assert!(regex("1@foxmail.com"));
+        assert!(!regex("a1@qq.com"));
+    }
+
+    #[test]
+    fn test_domain_rules() {
+        let rules: RuleSet = "DOMAIN,Example.com.\nDOMAIN-SUFFIX,example.org"
+            .parse()
+            .unwrap();
+        let resolver = OfflineResolver;
+        let matches = |i: usize, email| {
+            let rule = &rules.rules()[i].rule;
+            rule.find_match(email, &resolver).unwrap().is_some()
+        };
+        assert!(matches(0, "user@example.com"));
+        assert!(matches(0, "user@EXAMPLE.COM."));
+        assert!(!matches(0, "user@mail.example.com"));
+        assert!(!matches(0, "user@notexample.com"));
+
+        assert!(matches(1, "user@example.org"));
+        assert!(matches(1, "user@mail.Example.org."));
+        assert!(!matches(1, "user@notexample.org"));
+        assert!(!matches(1, "user@example.org.evil.net"));
+
+        assert!("DOMAIN,".parse::<RuleSet>().is_err());
+        assert!("DOMAIN-SUFFIX,a@b.com".parse::<RuleSet>().is_err());
+    }
+
+    #[test]
+    fn test_mx_rules() {
+        let fixture: StaticResolver = "gmail.com aspmx.l.google.com alt1.aspmx.l.google.com\n\
+             itsusinn.eu.org route1.mx.cloudflare.net\n\
+             evil.org mx.cloudflare.net.evil.org\n"
+            .parse()
+            .unwrap();
+        let rules: RuleSet = "MX-RECORD,*aspmx.l.google.com\nMX-SUFFIX,MX.cloudflare.net.\n"
+            .parse()
+            .unwrap();
+        let matched = |i: usize, email| {
+            let rule = &rules.rules()[i].rule;
+            match rule.find_match(email, &fixture).unwrap() {
+                Some(Match::MxHost(host)) => Some(host),
+                _ => None,
+            }
+        };
+        assert_eq!(
+            matched(0, "a@gmail.com").as_deref(),
+            Some("aspmx.l.google.com")
+        );
+        assert_eq!(matched(0, "a@itsusinn.eu.org"), None);
+        assert_eq!(
+            matched(1, "a@itsusinn.eu.org").as_deref(),
+            Some("route1.mx.cloudflare.net")
+        );
+        assert_eq!(matched(1, "a@evil.org"), None);
+    }
+
+    #[test]
+    fn test_ns_and_ip_rules() {
+        let fixture: StaticResolver = "corp.example mx.corp.example\n\
+             corp.example NS ns1.hosted-dns.net ns2.hosted-dns.net\n\
+             mx.corp.example A 198.51.100.25\n\
+             bare.example A 203.0.113.5\n\
+             v6.example mx.v6.example\n\
+             mx.v6.example AAAA 2001:db8::25\n"
+            .parse()
+            .unwrap();
+        let rules: RuleSet = "NS-RECORD,ns?.hosted-dns.net\n\
+             MX-IP,198.51.100.0/24\n\
+             MX-IP,203.0.113.5\n\
+             MX-IP,2001:db8::/32\n"
+            .parse()
+            .unwrap();
+        let matched = |i: usize, email| rules.rules()[i].rule.find_match(email, &fixture).unwrap();
+        assert_eq!(
+            matched(0, "a@corp.example"),
+            Some(Match::NsHost("ns1.hosted-dns.net".to_string()))
+        );
+        // a subdomain without its own NS records uses its zone's
+        assert!(matched(0, "a@mail.corp.example").is_some());
+        assert_eq!(matched(0, "a@bare.example"), None);
+        assert_eq!(
+            matched(1, "a@corp.example"),
+            Some(Match::MxAddress {
+                host: "mx.corp.example".to_string(),
+                ip: "198.51.100.25".to_string()
+            })
+        );
+        assert_eq!(matched(1, "a@v6.example"), None);
+        // the implicit MX of a domain without MX records is the domain
+        assert!(matched(2, "a@bare.example").is_some());
+        assert!(matched(3, "a@v6.example").is_some());
+
+        // a failed address lookup of one MX host doesn't hide another's match
+        struct Flaky(StaticResolver);
+        impl MxResolver for Flaky {
+            fn lookup(&self, domain: &str, kind: RecordType) -> Result<Vec<String>> {
+                if domain == "mx1.split.example" && kind == RecordType::Address {
+                    bail!("SERVFAIL");
+                }
+                self.0.lookup(domain, kind)
+            }
+        }
+        let flaky = Flaky(
+            "split.example mx1.split.example mx2.split.example\n\
+             mx2.split.example A 198.51.100.7\n"
+                .parse()
+                .unwrap(),
+        );
+        let ip_rule = |i: usize| &rules.rules()[i].rule;
+        assert!(matches!(
+            ip_rule(1).find_match("a@split.example", &flaky),
+            Result::Ok(Some(Match::MxAddress { .. }))
+        ));
+        assert!(ip_rule(2).find_match("a@split.example", &flaky).is_err());
+
+        assert!("MX-IP,198.51.100.0/33".parse::<RuleSet>().is_err());
+        assert!("MX-IP,mx.corp.example".parse::<RuleSet>().is_err());
+    }
+
+    #[test]
+    fn test_no_mail_rule() {
+        let fixture: StaticResolver = "gmail.com aspmx.l.google.com\n\
+             example.com A 192.0.2.1\n\
+             example.net MX .\n\
+             example.net A 192.0.2.2\n\
+             gmial.com NXDOMAIN\n"
+            .parse()
+            .unwrap();
+        let rules: RuleSet = "NO-MAIL\nMX-RECORD,example.com\n".parse().unwrap();
+        let matched = |i: usize, email| rules.rules()[i].rule.find_match(email, &fixture).unwrap();
+        assert_eq!(matched(0, "a@gmail.com"), None);
+        // no MX, but the A record is the implicit mail host
+        assert_eq!(matched(0, "a@example.com"), None);
+        assert_eq!(
+            matched(1, "a@Example.com"),
+            Some(Match::MxHost("example.com".to_string()))
+        );
+        assert_eq!(
+            matched(0, "a@example.net"),
+            Some(Match::NoMail("null MX".to_string()))
+        );
+        assert_eq!(
+            matched(0, "a@gmial.com"),
+            Some(Match::NoMail("domain does not exist".to_string()))
+        );
+        assert_eq!(
+            matched(0, "a@gmail.org"),
+            Some(Match::NoMail("no MX or address records".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_toml_rules() {
+        let rules = RuleSet::from_toml(
+            r#"
+[[rule]]
+id = "personal"
+kind = "domain-suffix"
+pattern = "gmail.com"
+message = "Use your @corp.com address"
+severity = "notice"
+
+[[rule]]
+kind = "NO-MAIL"
+
+[[rule]]
+pattern = "bot@corp.com"
+allow = true
+"#,
+        )
+        .unwrap();
+        let rules = rules.rules();
+        assert_eq!(rules[0].source.line, 2);
+        assert_eq!(rules[0].source.text, "DOMAIN-SUFFIX,gmail.com");
+        assert_eq!(rules[0].metadata.severity, Severity::Notice);
+        assert_eq!(
+            rules[0].metadata.message.as_deref(),
+            Some("Use your @corp.com address")
+        );
+        assert!(matches!(rules[1].rule, Rule::NoMail));
+        assert_eq!(rules[2].source.text, "!bot@corp.com");
+        assert!(rules[2].allow);
+
+        let error = RuleSet::from_toml(
+            "[[rule]]\nid = 'a'\nkind = 'MX'\npattern = 'x'\n\n\
+             [[rule]]\nid = 'a'\npattern = '*'\n",
+        )
+        .err()
+        .unwrap()
+        .to_string();
+        assert_eq!(
+            error,
+            "line 1: invalid rule 'MX,x': unknown rule kind 'MX'\n\
+             line 6: invalid rule '*': duplicate rule id 'a', first used at line 1"
+        );
+        assert!(RuleSet::from_toml("[[rule]]\npatern = '*'\n").is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+            path
+        };
+        write("org/base.txt", "*@localhost\ninclude extra.toml\n");
+        write("org/extra.toml", "[[rule]]\npattern = 'root@*'\n");
+        let repo = write("rules.txt", "include org/base.txt\n*@gmail.com\n");
+        let local = write("local.txt", "include org/base.txt\n*@qq.com\n");
+
+        let rules = RuleSet::from_files([&repo, &local]).unwrap();
+        let rules: Vec<_> = rules
+            .rules()
+            .iter()
+            .map(|r| (r.source.file.clone().unwrap(), r.source.text.as_str()))
+            .collect();
+        assert_eq!(
+            rules,
+            [
+                (dir.path().join("org/base.txt"), "*@localhost"),
+                (dir.path().join("org/extra.toml"), "root@*"),
+                (repo.clone(), "*@gmail.com"),
+                (local.clone(), "*@qq.com"),
+            ]
+        );
+
+        write("org/extra.toml", "include = ['../rules.txt']\n");
+        let error = format!("{:#}", RuleSet::from_file(&repo).err().unwrap());
+        assert!(error.contains("include cycle: "));
+        assert!(error.ends_with("rules.txt"));
+
+        write("org/extra.toml", "[[rule]]\npattern = '[x'\n");
+        let error = format!("{:#}", RuleSet::from_file(&repo).err().unwrap());
+        assert!(error.contains("line 2: invalid rule 'include extra.toml': "));
+        assert!(error.contains("line 1: invalid rule '[x': unclosed `[`"));
+
+        // ids are unique across files, whether given with -r or included
+        write("org/extra.toml", "[[rule]]\nid = 'a'\npattern = 'root@*'\n");
+        let other = write("other.toml", "[[rule]]\nid = 'a'\npattern = '*@qq.com'\n");
+        let error = format!("{:#}", RuleSet::from_files([&repo, &other]).err().unwrap());
+        assert!(
+            error
+                .contains("line 1: invalid rule '*@qq.com': duplicate rule id 'a', first used at ")
+        );
+        assert!(error.ends_with("extra.toml:1"));
+        write(
+            "org/base.txt",
+            "include extra.toml\ninclude ../other.toml\n",
+        );
+        let error = format!("{:#}", RuleSet::from_file(&repo).err().unwrap());
+        assert!(error.contains("duplicate rule id 'a'"));
+
+        assert!("include base.txt".parse::<RuleSet>().is_err());
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let rules = "*@ok.com\nREGEX,(unclosed\n# comment\n[abc@x.org\n";
+        let error = rules.parse::<RuleSet>().err().unwrap().to_string();
+        assert!(error.starts_with("line 2: invalid rule 'REGEX,(unclosed'"));
+        assert!(error.contains("\nline 4: invalid rule '[abc@x.org': unclosed `[`"));
+        assert!(!error.contains("line 1:"));
+
+        let error = "MX-RECORD,".parse::<RuleSet>().err().unwrap().to_string();
+        assert_eq!(error, "line 1: invalid rule 'MX-RECORD,': empty MX target");
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{Report, Severity, Violation};
+use serde_json::{Value, json};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+const SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Render a report as a SARIF 2.1.0 log.
+///
+/// Each rule that produced a violation becomes a rule descriptor, and each
+/// offending commit a result. Results point at the rule's line in its rules
+/// file and name the commit as a logical location. `rules_path` is the main
+/// rules file, whose rules get ids of the form `rules-line-N`.
+///
+/// Rules files are referred to relative to the working directory, so ids
+/// and locations are the same on every machine that runs the check from
+/// the same place.
+pub fn render(report: &Report, rules_path: &Path) -> Value {
+    let paths = Paths {
+        rules_path,
+        base: env::current_dir().and_then(fs::canonicalize).ok(),
+    };
+    let mut rules: Vec<&Violation> = Vec::new();
+    for v in &report.violations {
+        if !rules.iter().any(|r| same_rule(r, v)) {
+            rules.push(v);
+        }
+    }
+    rules.sort_unstable_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+
+    let descriptors: Vec<_> = rules
+        .iter()
+        .map(|r| {
+            let description = match &r.metadata.message {
+                Some(message) => message.clone(),
+                None => format!("Commit email matches rule `{}`", r.rule),
+            };
+            let mut descriptor = json!({
+                "id": paths.rule_id(r),
+                "name": r.rule,
+                "shortDescription": { "text": description },
+            });
+            if let Some(url) = &r.metadata.url {
+                descriptor["helpUri"] = json!(url);
+            }
+            descriptor
+        })
+        .collect();
+
+    let results: Vec<_> = report
+        .violations
+        .iter()
+        .flat_map(|v| {
+            let index = rules
+                .iter()
+                .position(|r| same_rule(r, v))
+                .unwrap_or_default();
+            violation_results(v, index, &paths)
+        })
+        .collect();
+
+    json!({
+        "$schema": SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": descriptors,
+                },
+            },
+            "results": results,
+        }],
+    })
+}
+
+fn same_rule(a: &Violation, b: &Violation) -> bool {
+    (&a.file, a.line) == (&b.file, b.line)
+}
+
+struct Paths<'a> {
+    rules_path: &'a Path,
+    /// The canonical working directory
+    base: Option<PathBuf>,
+}
+
+impl Paths<'_> {
+    /// The rule's id from a TOML rules file, or else one derived from its
+    /// file and line.
+    fn rule_id(&self, v: &Violation) -> String {
+        match (&v.metadata.id, &v.file) {
+            (Some(id), _) => id.clone(),
+            (None, Some(file)) if file != self.rules_path => {
+                let name = match self.relative(file) {
+                    Some(relative) => segments(&relative).join("/"),
+                    None => file.file_name().unwrap_or_default().display().to_string(),
+                };
+                format!("{name}-line-{}", v.line)
+            }
+            (None, _) => format!("rules-line-{}", v.line),
+        }
+    }
+
+    /// `file` relative to the working directory, if it is inside it.
+    fn relative(&self, file: &Path) -> Option<PathBuf> {
+        let file = fs::canonicalize(file).ok()?;
+        Some(file.strip_prefix(self.base.as_ref()?).ok()?.to_path_buf())
+    }
+
+    /// A URI reference for `file`: a relative one if it is inside the
+    /// working directory, or else a `file:` URI.
+    fn uri(&self, file: &Path) -> String {
+        if let Some(relative) = self.relative(file) {
+            return segments(&relative)
+                .iter()
+                .map(|s| encode(s))
+                .collect::<Vec<_>>()
+                .join("/");
+        }
+        let absolute = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let path = segments(&absolute)
+            .iter()
+            .map(|s| {
+                if s.ends_with(':') {
+                    s.clone()
+                } else {
+                    encode(s)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        format!("file:///{}", path.trim_start_matches('/'))
+    }
+}
+
+/// The names of a path's components, without any root or prefix markers.
+fn segments(path: &Path) -> Vec<String> {
+    path.to_string_lossy()
+        .trim_start_matches(r"\\?\")
+        .split(['/', '\\'])
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Percent-encode everything but unreserved URI characters, so that no
+/// segment reads as a scheme or contains a delimiter.
+fn encode(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Notice => "note",
+    }
+}
+
+fn violation_results(v: &Violation, rule_index: usize, paths: &Paths) -> Vec<Value> {
+    let physical = json!({
+        "artifactLocation": { "uri": paths.uri(v.file.as_deref().unwrap_or(paths.rules_path)) },
+        "region": { "startLine": v.line },
+    });
+    let message = json!({ "text": v.describe() });
+    let result = |logical: Value, fingerprint: String| {
+        json!({
+            "ruleId": paths.rule_id(v),
+            "ruleIndex": rule_index,
+            "level": level(v.metadata.severity),
+            "message": message,
+            "locations": [{
+                "physicalLocation": physical,
+                "logicalLocations": logical,
+            }],
+            "partialFingerprints": { "commitEmail/v1": fingerprint },
+            "properties": { "email": v.email },
+        })
+    };
+
+    if v.origins.is_empty() {
+        return vec![result(json!([]), v.email.clone())];
+    }
+    v.origins
+        .iter()
+        .map(|origin| {
+            let logical = json!([{
+                "name": origin.to_string(),
+                "fullyQualifiedName": origin.commit,
+                "kind": "commit",
+            }]);
+            result(
+                logical,
+                format!("{}:{}:{}", v.email, origin.commit, origin.role),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Paths, encode};
+    use std::{env, fs, path::Path};
+
+    #[test]
+    fn test_uri() {
+        assert_eq!(encode("C:"), "C%3A");
+        assert_eq!(encode("my rules#1.txt"), "my%20rules%231.txt");
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("org rules.txt");
+        fs::write(&file, "").unwrap();
+        let outside = Paths {
+            rules_path: Path::new("rules.txt"),
+            base: env::current_dir().and_then(fs::canonicalize).ok(),
+        };
+        let uri = outside.uri(&file);
+        assert!(uri.starts_with("file:///"));
+        assert!(uri.ends_with("/org%20rules.txt"));
+
+        let inside = Paths {
+            base: fs::canonicalize(dir.path()).ok(),
+            ..outside
+        };
+        assert_eq!(inside.uri(&file), "org%20rules.txt");
+    }
+}